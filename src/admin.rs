@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use warp::{http::StatusCode, Filter};
+
+use crate::registry::Registry;
+
+/*
+Read-only views of the live sessions for local tools. Not covered by the
+CORS header, so web pages cannot read other applications' state.
+GET /admin/sessions
+[{"activeSet":"Part Studio","focused":true,"instance":3847201,"name":"Onshape","profile":"onshape"}]
+GET /admin/sessions/3847201/commands
+{"activeSet":"Part Studio","images":{...},"sets":[{"id":"Part Studio","label":"Part Studio","nodes":[...]}]}
+ */
+pub fn routes(
+    registry: Arc<Registry>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let list_registry = registry.clone();
+    let sessions = warp::path!("admin" / "sessions")
+        .and(warp::get())
+        .map(move || {
+            let (instances, focused) = list_registry.instances();
+            let sessions: Vec<_> = instances
+                .into_iter()
+                .filter_map(|instance| {
+                    list_registry.with_info(instance, |info| {
+                        json!({
+                            "instance": instance,
+                            "name": info.name,
+                            "focused": focused == Some(instance),
+                            "profile": info.profile,
+                            "activeSet": info.commands.active_set,
+                        })
+                    })
+                })
+                .collect();
+            warp::reply::json(&sessions)
+        });
+
+    let commands = warp::path!("admin" / "sessions" / u32 / "commands")
+        .and(warp::get())
+        .map(move |instance| {
+            match registry.with_info(instance, |info| warp::reply::json(&info.commands)) {
+                Some(commands) => warp::reply::with_status(commands, StatusCode::OK),
+                None => warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "no such session" })),
+                    StatusCode::NOT_FOUND,
+                ),
+            }
+        });
+
+    sessions.or(commands)
+}
@@ -0,0 +1,259 @@
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose, RcgenError,
+};
+use time::{Duration, OffsetDateTime};
+
+/// Names the 3Dconnexion SDK and local tools connect with
+pub const SERVER_NAMES: [&str; 3] = ["127.51.68.120", "localhost", "127.0.0.1"];
+
+const CA_NAME: &str = "spacenav-web local CA";
+const CA_VALIDITY_DAYS: i64 = 3650;
+/// Browsers refuse leaf certificates valid for longer than 398 days
+const SERVER_VALIDITY_DAYS: i64 = 397;
+/// Startup warns once the server certificate expires within this many days
+pub const EXPIRY_WARNING_DAYS: i64 = 30;
+
+#[derive(Debug)]
+pub enum CertError {
+    Io(PathBuf, io::Error),
+    Generate(RcgenError),
+    Parse(PathBuf, String),
+}
+impl fmt::Display for CertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CertError::Io(path, err) => write!(f, "{}: {err}", path.display()),
+            CertError::Generate(err) => write!(f, "cannot generate certificate: {err}"),
+            CertError::Parse(path, err) => write!(f, "cannot parse {}: {err}", path.display()),
+        }
+    }
+}
+impl From<RcgenError> for CertError {
+    fn from(err: RcgenError) -> CertError {
+        CertError::Generate(err)
+    }
+}
+
+/// Where the CA and the server certificate are written
+pub struct CertPaths {
+    pub ca_cert: PathBuf,
+    pub ca_key: PathBuf,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+impl CertPaths {
+    /// The CA lives in `dir`, the server certificate wherever the config
+    /// expects it
+    pub fn new(dir: &Path, cert: &Path, key: &Path) -> CertPaths {
+        CertPaths {
+            ca_cert: dir.join("ca.crt"),
+            ca_key: dir.join("ca.key"),
+            cert: cert.to_path_buf(),
+            key: key.to_path_buf(),
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<String, CertError> {
+    fs::read_to_string(path).map_err(|err| CertError::Io(path.to_path_buf(), err))
+}
+
+/// Writes a file, private keys only readable by the owner
+fn write(path: &Path, contents: &str, mode: u32) -> Result<(), CertError> {
+    let err = |err| CertError::Io(path.to_path_buf(), err);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(err)?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(err)
+}
+
+fn new_ca() -> Result<Certificate, CertError> {
+    let mut params = CertificateParams::default();
+    params.distinguished_name.push(DnType::CommonName, CA_NAME);
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    params.not_before = OffsetDateTime::now_utc() - Duration::days(1);
+    params.not_after = OffsetDateTime::now_utc() + Duration::days(CA_VALIDITY_DAYS);
+    Ok(Certificate::from_params(params)?)
+}
+
+/// Reuses the CA from an earlier run, so browsers which trust it already
+/// accept the new server certificate
+fn load_ca(paths: &CertPaths) -> Result<Option<Certificate>, CertError> {
+    if !paths.ca_cert.is_file() || !paths.ca_key.is_file() {
+        return Ok(None);
+    }
+    let key = KeyPair::from_pem(&read(&paths.ca_key)?)?;
+    let params = CertificateParams::from_ca_cert_pem(&read(&paths.ca_cert)?, key)?;
+    Ok(Some(Certificate::from_params(params)?))
+}
+
+fn new_server_cert() -> Result<Certificate, CertError> {
+    let mut params = CertificateParams::new(SERVER_NAMES.map(String::from).to_vec());
+    params
+        .distinguished_name
+        .push(DnType::CommonName, SERVER_NAMES[0]);
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.not_before = OffsetDateTime::now_utc() - Duration::days(1);
+    params.not_after = OffsetDateTime::now_utc() + Duration::days(SERVER_VALIDITY_DAYS);
+    Ok(Certificate::from_params(params)?)
+}
+
+/// Creates the CA unless it exists, then a server certificate signed by it.
+/// Returns whether the CA is new and has to be trusted.
+pub fn generate(paths: &CertPaths) -> Result<bool, CertError> {
+    let (ca, created) = match load_ca(paths)? {
+        Some(ca) => (ca, false),
+        None => (new_ca()?, true),
+    };
+    if created {
+        write(&paths.ca_key, &ca.serialize_private_key_pem(), 0o600)?;
+        write(&paths.ca_cert, &ca.serialize_pem()?, 0o644)?;
+    }
+
+    let server = new_server_cert()?;
+    // Send the chain, some clients want to see the issuer
+    let chain = server.serialize_pem_with_signer(&ca)? + &read(&paths.ca_cert)?;
+    write(&paths.key, &server.serialize_private_key_pem(), 0o600)?;
+    write(&paths.cert, &chain, 0o644)?;
+    Ok(created)
+}
+
+/// Seconds from now until the first certificate in a PEM file expires,
+/// negative once it has
+pub fn seconds_left(path: &Path) -> Result<i64, CertError> {
+    let pem = read(path)?;
+    let parse_err = |err: String| CertError::Parse(path.to_path_buf(), err);
+    let (_, pem) = x509_parser::pem::parse_x509_pem(pem.as_bytes())
+        .map_err(|err| parse_err(err.to_string()))?;
+    let cert = pem.parse_x509().map_err(|err| parse_err(err.to_string()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    Ok(cert.validity().not_after.timestamp() - now)
+}
+
+/// Checks that the server certificate and key can be loaded. The TLS
+/// listener would panic on either being missing or broken.
+pub fn check_files(cert: &Path, key: &Path) -> Result<(), CertError> {
+    seconds_left(cert)?;
+    let pem = read(key)?;
+    let parse_err = |err: String| CertError::Parse(key.to_path_buf(), err);
+    let (_, pem) = x509_parser::pem::parse_x509_pem(pem.as_bytes())
+        .map_err(|err| parse_err(err.to_string()))?;
+    // The PKCS#8 and RSA encodings are the ones the TLS listener reads
+    match pem.label.as_str() {
+        "PRIVATE KEY" | "RSA PRIVATE KEY" => Ok(()),
+        label => Err(parse_err(format!("expected a private key, found {label}"))),
+    }
+}
+
+/// Prints a warning when the server certificate expires soon or has
+/// expired. The server still starts, the browser shows the actual error.
+pub fn check_expiry(path: &Path) {
+    match seconds_left(path) {
+        Ok(left) if left <= 0 => tracing::warn!(
+            path = %path.display(),
+            "certificate has expired, run `spacenav-web cert generate`"
+        ),
+        Ok(left) if left < EXPIRY_WARNING_DAYS * 86400 => tracing::warn!(
+            path = %path.display(),
+            days = left / 86400,
+            "certificate expires soon, run `spacenav-web cert generate`"
+        ),
+        Ok(_) => {}
+        Err(err) => tracing::warn!(%err, "cannot check certificate"),
+    }
+}
+
+/// NSS databases of Chromium (`~/.pki/nssdb`) and of every Firefox profile
+pub fn nss_databases(home: &Path) -> Vec<PathBuf> {
+    let mut dbs = vec![home.join(".pki/nssdb")];
+    if let Ok(profiles) = fs::read_dir(home.join(".mozilla/firefox")) {
+        dbs.extend(
+            profiles
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|dir| dir.join("cert9.db").is_file()),
+        );
+    }
+    dbs.retain(|db| db.is_dir());
+    dbs
+}
+
+/// Adds the CA to an NSS database as trusted for TLS servers
+pub fn install_nss(db: &Path, ca_cert: &Path) -> io::Result<()> {
+    let status = Command::new("certutil")
+        .arg("-d")
+        .arg(format!("sql:{}", db.display()))
+        .args(["-A", "-t", "C,,", "-n", CA_NAME, "-i"])
+        .arg(ca_cert)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("certutil exited with {status}")))
+    }
+}
+
+/// How to trust the CA by hand
+pub fn instructions(ca_cert: &Path) -> String {
+    format!(
+        "Trust {ca} in your browser to let it connect to spacenav-web:\n\
+         \x20 Chromium: certutil -d sql:$HOME/.pki/nssdb -A -t \"C,,\" -n \"{CA_NAME}\" -i {ca}\n\
+         \x20 Firefox:  Settings > Privacy & Security > View Certificates > Authorities > Import\n\
+         or run `spacenav-web cert generate --install`.",
+        ca = ca_cert.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_and_renew() {
+        let dir = std::env::temp_dir().join(format!("spacenav-web-cert-{}", std::process::id()));
+        let paths = CertPaths::new(&dir, &dir.join("server.crt"), &dir.join("server.key"));
+
+        assert!(generate(&paths).unwrap());
+        let ca = fs::read_to_string(&paths.ca_cert).unwrap();
+        let left = seconds_left(&paths.cert).unwrap();
+        assert!(left > (SERVER_VALIDITY_DAYS - 1) * 86400);
+        assert!(left <= SERVER_VALIDITY_DAYS * 86400);
+
+        let chain = fs::read_to_string(&paths.cert).unwrap();
+        let (_, leaf) = x509_parser::pem::parse_x509_pem(chain.as_bytes()).unwrap();
+        let leaf = leaf.parse_x509().unwrap();
+        let names = leaf.subject_alternative_name().unwrap().unwrap();
+        assert_eq!(names.value.general_names.len(), SERVER_NAMES.len());
+        assert!(chain.ends_with(&ca));
+
+        // A second run keeps the CA the browser already trusts
+        assert!(!generate(&paths).unwrap());
+        assert_eq!(fs::read_to_string(&paths.ca_cert).unwrap(), ca);
+
+        assert!(check_files(&paths.cert, &paths.key).is_ok());
+        assert!(check_files(&paths.cert, &paths.cert).is_err());
+        assert!(check_files(&paths.cert, &dir.join("missing.key")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    sync::Notify,
+    time::{Duration, Instant},
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    trace::{Direction, TraceEntry},
+    wamp,
+};
+
+/// How long to wait for the server. A session waiting on one of its own
+/// calls only answers after that call timed out.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to keep listening for server frames after the last client frame
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How the server answered a client call
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Reply {
+    Result,
+    Error(String),
+}
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Result => write!(f, "a result"),
+            Reply::Error(uri) => write!(f, "error {uri}"),
+        }
+    }
+}
+
+/// A call from the server into the client's controller object, identified by
+/// method, property and how many of the same came before it. Call ids are
+/// random, so this is what relates recorded calls to live ones.
+type ServerCall = (String, String, usize);
+
+#[derive(Default)]
+struct Calls {
+    seen: HashMap<(String, String), usize>,
+}
+impl Calls {
+    /// Returns the call carried by a frame, if it is a `self:*` call
+    fn identify(&mut self, msg: &wamp::Message) -> Option<(String, ServerCall)> {
+        let event = match msg {
+            wamp::Message::Event { event, .. } => event,
+            _ => return None,
+        };
+        if event[0] != wamp::MessageType::Call as u64 {
+            return None;
+        }
+        let id = event[1].as_str()?.to_string();
+        let method = event[2].as_str()?.to_string();
+        let key = event[4].as_str().unwrap_or_default().to_string();
+        let count = self.seen.entry((method.clone(), key.clone())).or_default();
+        *count += 1;
+        Some((id, (method, key, *count)))
+    }
+}
+
+/// What the live server sent so far
+#[derive(Default)]
+struct Live {
+    instance: Option<u32>,
+    calls: Calls,
+    /// Live call id of every server call
+    call_ids: HashMap<ServerCall, String>,
+    /// Every server call in order
+    call_log: Vec<ServerCall>,
+    replies: HashMap<String, Reply>,
+}
+
+struct Shared {
+    live: Mutex<Live>,
+    changed: Notify,
+}
+impl Shared {
+    /// Waits until `f` finds what it looks for in the live state
+    async fn wait_for<T>(&self, timeout: Duration, f: impl Fn(&Live) -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let changed = self.changed.notified();
+            if let Some(value) = f(&self.live.lock().unwrap()) {
+                return Some(value);
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Client frames sent to the server
+    pub sent: usize,
+    /// Client calls answered differently than in the recording
+    pub mismatches: Vec<String>,
+    /// Server calls which happened a different number of times, informative
+    /// since they depend on timing and device input
+    pub call_differences: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Connect(String),
+    /// The trace holds several sessions and none was chosen
+    AmbiguousInstance(Vec<u32>),
+    NoSuchInstance(u32),
+}
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Connect(err) => write!(f, "cannot connect: {err}"),
+            ReplayError::AmbiguousInstance(instances) => {
+                write!(f, "trace has several sessions, choose one of {instances:?}")
+            }
+            ReplayError::NoSuchInstance(instance) => write!(f, "no session {instance} in trace"),
+        }
+    }
+}
+
+/// Picks the frames of one session from a trace
+pub fn session_frames(
+    entries: &[TraceEntry],
+    instance: Option<u32>,
+) -> Result<Vec<TraceEntry>, ReplayError> {
+    let mut instances: Vec<u32> = entries.iter().map(|e| e.instance).collect();
+    instances.sort_unstable();
+    instances.dedup();
+    let instance = match (instance, &instances[..]) {
+        (Some(instance), _) if instances.contains(&instance) => instance,
+        (Some(instance), _) => return Err(ReplayError::NoSuchInstance(instance)),
+        (None, [instance]) => *instance,
+        (None, _) => return Err(ReplayError::AmbiguousInstance(instances)),
+    };
+    Ok(entries
+        .iter()
+        .filter(|e| e.instance == instance)
+        .cloned()
+        .collect())
+}
+
+/// Plays the client side of a recorded session against a running proxy with
+/// the recorded timing, then compares the server's behaviour to the recording.
+///
+/// Replies to server calls are sent once the live server made the matching
+/// call, with its call id, and the recorded instance is replaced by the live
+/// one.
+pub async fn replay(frames: &[TraceEntry], url: &str) -> Result<Report, ReplayError> {
+    let (socket, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|err| ReplayError::Connect(err.to_string()))?;
+    let (mut sink, mut stream) = socket.split();
+
+    let recorded_instance = frames.first().map_or(0, |f| f.instance);
+    let mut recorded_calls = Calls::default();
+    let mut recorded_ids: HashMap<String, ServerCall> = HashMap::new();
+    let mut recorded_log = Vec::new();
+    let mut recorded_replies = HashMap::new();
+    for frame in frames.iter().filter(|f| f.dir == Direction::Out) {
+        let msg = match wamp::Message::from_text(&frame.frame) {
+            Ok(msg) => msg,
+            Err(_) => continue,
+        };
+        if let Some((id, call)) = recorded_calls.identify(&msg) {
+            recorded_log.push(call.clone());
+            recorded_ids.insert(id, call);
+        } else if let Some((call_id, reply)) = reply_of(&msg) {
+            recorded_replies.insert(call_id, reply);
+        }
+    }
+
+    let shared = Arc::new(Shared {
+        live: Mutex::new(Live::default()),
+        changed: Notify::new(),
+    });
+    let reader_shared = shared.clone();
+    let reader = tokio::spawn(async move {
+        while let Some(Ok(msg)) = stream.next().await {
+            let text = match msg {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let msg = match wamp::Message::from_text(&text) {
+                Ok(msg) => msg,
+                Err(_) => continue,
+            };
+            let mut live = reader_shared.live.lock().unwrap();
+            if let Some((id, call)) = live.calls.identify(&msg) {
+                live.call_log.push(call.clone());
+                live.call_ids.insert(call, id);
+            } else if let Some((call_id, reply)) = reply_of(&msg) {
+                if let wamp::Message::CallResult { result, .. } = &msg {
+                    if let Some(instance) = result["instance"].as_u64() {
+                        live.instance = u32::try_from(instance).ok();
+                    }
+                }
+                live.replies.insert(call_id, reply);
+            }
+            drop(live);
+            reader_shared.changed.notify_waiters();
+        }
+    });
+
+    let mut report = Report::default();
+    let mut client_calls = Vec::new();
+    let recorded_topic = format!("3dcontroller/{recorded_instance}");
+    let start = Instant::now();
+    let first = frames.first().map_or(0, |f| f.time);
+
+    for frame in frames.iter().filter(|f| f.dir == Direction::In) {
+        // Frames are timed before the trace is locked, so they can be written
+        // slightly out of order
+        let offset = frame.time.saturating_sub(first);
+        tokio::time::sleep_until(start + Duration::from_millis(offset)).await;
+
+        let mut msg = match wamp::Message::from_text(&frame.frame) {
+            Ok(msg) => msg,
+            // Broken frames are part of what can be replayed
+            Err(_) => {
+                send(&mut sink, frame.frame.clone()).await?;
+                report.sent += 1;
+                continue;
+            }
+        };
+
+        match &mut msg {
+            wamp::Message::Call { call_id, .. } => client_calls.push(call_id.clone()),
+            wamp::Message::CallResult { call_id, .. }
+            | wamp::Message::CallError { call_id, .. } => {
+                if let Some(call) = recorded_ids.get(call_id) {
+                    let live_id = shared
+                        .wait_for(REPLY_TIMEOUT, |live| live.call_ids.get(call).cloned())
+                        .await;
+                    match live_id {
+                        Some(live_id) => *call_id = live_id,
+                        None => {
+                            report.mismatches.push(format!(
+                                "server never called {} {} #{}",
+                                call.0, call.1, call.2
+                            ));
+                            continue;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let mut text = msg.to_text();
+        if text.contains(&recorded_topic) {
+            match shared.wait_for(REPLY_TIMEOUT, |live| live.instance).await {
+                Some(instance) => {
+                    text = text.replace(&recorded_topic, &format!("3dcontroller/{instance}"))
+                }
+                None => {
+                    report
+                        .mismatches
+                        .push("server never created a 3dcontroller".to_string());
+                    continue;
+                }
+            }
+        }
+        send(&mut sink, text).await?;
+        report.sent += 1;
+    }
+
+    for call_id in &client_calls {
+        let reply = shared
+            .wait_for(REPLY_TIMEOUT, |live| live.replies.get(call_id).cloned())
+            .await;
+        match (recorded_replies.get(call_id), reply) {
+            (Some(expected), Some(reply)) if *expected != reply => report
+                .mismatches
+                .push(format!("call {call_id}: expected {expected}, got {reply}")),
+            (Some(expected), None) => report
+                .mismatches
+                .push(format!("call {call_id}: expected {expected}, got no reply")),
+            _ => {}
+        }
+    }
+    tokio::time::sleep(DRAIN_TIMEOUT).await;
+    let _ = sink.close().await;
+    reader.abort();
+
+    let live = shared.live.lock().unwrap();
+    report.call_differences = compare_calls(&recorded_log, &live.call_log);
+    Ok(report)
+}
+
+async fn send(
+    sink: &mut (impl futures_util::Sink<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin),
+    text: String,
+) -> Result<(), ReplayError> {
+    sink.send(Message::Text(text))
+        .await
+        .map_err(|err| ReplayError::Connect(err.to_string()))
+}
+
+fn reply_of(msg: &wamp::Message) -> Option<(String, Reply)> {
+    match msg {
+        wamp::Message::CallResult { call_id, .. } => Some((call_id.clone(), Reply::Result)),
+        wamp::Message::CallError {
+            call_id, error_uri, ..
+        } => Some((call_id.clone(), Reply::Error(error_uri.clone()))),
+        _ => None,
+    }
+}
+
+/// Counts of each kind of server call, wherever they differ
+fn compare_calls(recorded: &[ServerCall], live: &[ServerCall]) -> Vec<String> {
+    let count = |calls: &[ServerCall]| {
+        let mut counts: HashMap<(String, String), usize> = HashMap::new();
+        for (method, key, _) in calls {
+            *counts.entry((method.clone(), key.clone())).or_default() += 1;
+        }
+        counts
+    };
+    let (recorded, live) = (count(recorded), count(live));
+    let mut kinds: Vec<_> = recorded
+        .keys()
+        .chain(live.keys())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    kinds.sort();
+    kinds
+        .into_iter()
+        .filter_map(|kind| {
+            let (r, l) = (
+                recorded.get(kind).copied().unwrap_or(0),
+                live.get(kind).copied().unwrap_or(0),
+            );
+            (r != l).then(|| format!("{} {}: recorded {r}, live {l}", kind.0, kind.1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(instance: u32) -> TraceEntry {
+        TraceEntry {
+            time: 0,
+            instance,
+            dir: Direction::In,
+            frame: "[5,\"3dconnexion:3dcontroller\"]".to_string(),
+        }
+    }
+
+    #[test]
+    fn picks_session() {
+        let one = [entry(1), entry(1)];
+        assert_eq!(session_frames(&one, None).unwrap().len(), 2);
+        assert!(matches!(
+            session_frames(&one, Some(2)),
+            Err(ReplayError::NoSuchInstance(2))
+        ));
+
+        let two = [entry(1), entry(2), entry(1)];
+        assert!(matches!(
+            session_frames(&two, None),
+            Err(ReplayError::AmbiguousInstance(ref i)) if i == &[1, 2]
+        ));
+        assert_eq!(session_frames(&two, Some(2)).unwrap(), [entry(2)]);
+    }
+
+    #[test]
+    fn matches_calls_by_kind() {
+        let call = |id: &str, key: &str| wamp::Message::Event {
+            topic_uri: "3dconnexion:3dcontroller/1".to_string(),
+            event: serde_json::json!([2, id, "self:read", "", key]),
+        };
+        let mut calls = Calls::default();
+        let a = calls.identify(&call("a", "view.affine")).unwrap();
+        let b = calls.identify(&call("b", "view.target")).unwrap();
+        let c = calls.identify(&call("c", "view.affine")).unwrap();
+        assert_eq!(a.1, ("self:read".to_string(), "view.affine".to_string(), 1));
+        assert_eq!(b.1 .2, 1);
+        assert_eq!(c.1 .2, 2);
+
+        assert_eq!(
+            compare_calls(&[a.1.clone(), b.1.clone()], &[a.1, c.1]),
+            [
+                "self:read view.affine: recorded 1, live 2",
+                "self:read view.target: recorded 1, live 0"
+            ]
+        );
+    }
+}
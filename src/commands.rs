@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::properties::PropertyError;
+
+/// Below an action set, either a group of commands or a command
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Node {
+    Category {
+        id: String,
+        label: String,
+        nodes: Vec<Node>,
+    },
+    Action {
+        id: String,
+        label: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+    },
+}
+
+/// A set of commands the application offers in one context, e.g. one per
+/// kind of editor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ActionSet {
+    pub id: String,
+    pub label: String,
+    pub nodes: Vec<Node>,
+}
+
+/*
+[2,"0.h2jd78cvemc","3dx_rpc:update","3dconnexion:3dcontroller/6884113743086",
+ {"commands":{"activeSet":"Part Studio","tree":{"nodes":[{"id":"Part Studio","label":"Part Studio",
+  "nodes":[{"id":"CAT_VIEW","label":"View","nodes":[{"id":"ID_FIT","label":"Fit","description":"Fit all"}]}]}]}},
+  "images":[{"id":"ID_FIT","type":"image/png","data":"iVBORw0..."}]}]
+ */
+/// The application's command tree, as sent with `commands` and `images`
+/// updates
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Commands {
+    #[serde(rename = "activeSet")]
+    pub active_set: Option<String>,
+    pub sets: Vec<ActionSet>,
+    /// Button images by the id of the command they show
+    pub images: BTreeMap<String, Value>,
+}
+
+fn tree_error(key: &str) -> PropertyError {
+    PropertyError {
+        key: key.to_string(),
+        expected: "an action tree",
+    }
+}
+
+fn string_field(value: &Value, name: &str) -> Option<String> {
+    value.get(name).and_then(Value::as_str).map(str::to_string)
+}
+
+/// Nodes are told apart by shape: anything with children is a category.
+/// The SDK's `type` numbers differ between versions and are not needed.
+fn parse_node(value: &Value) -> Result<Node, PropertyError> {
+    let err = || tree_error("commands.tree");
+    let id = string_field(value, "id").ok_or_else(err)?;
+    let label = string_field(value, "label").unwrap_or_else(|| id.clone());
+    Ok(match value.get("nodes") {
+        Some(nodes) => Node::Category {
+            id,
+            label,
+            nodes: parse_nodes(nodes)?,
+        },
+        None => Node::Action {
+            id,
+            label,
+            description: string_field(value, "description"),
+        },
+    })
+}
+
+fn parse_nodes(value: &Value) -> Result<Vec<Node>, PropertyError> {
+    value
+        .as_array()
+        .ok_or_else(|| tree_error("commands.tree"))?
+        .iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// The tree is an `ActionTree` object with its sets in `nodes`, or just the
+/// array of sets
+fn parse_tree(value: &Value) -> Result<Vec<ActionSet>, PropertyError> {
+    let sets = value.get("nodes").unwrap_or(value);
+    sets.as_array()
+        .ok_or_else(|| tree_error("commands.tree"))?
+        .iter()
+        .map(|set| match parse_node(set)? {
+            Node::Category { id, label, nodes } => Ok(ActionSet { id, label, nodes }),
+            Node::Action { .. } => Err(tree_error("commands.tree")),
+        })
+        .collect()
+}
+
+impl Commands {
+    /// Applies the `commands` and `images` parts of a `3dx_rpc:update`
+    /// payload. A new tree replaces the old one as a whole.
+    pub fn update(&mut self, map: &Map<String, Value>) -> Result<(), PropertyError> {
+        if let Some(commands) = map.get("commands") {
+            let sets = commands.get("tree").map(parse_tree).transpose()?;
+            let active_set = match commands.get("activeSet") {
+                Some(Value::String(id)) => Some(Some(id.clone())),
+                Some(Value::Null) => Some(None),
+                Some(_) => {
+                    return Err(PropertyError {
+                        key: "commands.activeSet".to_string(),
+                        expected: "a string",
+                    })
+                }
+                None => None,
+            };
+            if let Some(sets) = sets {
+                self.sets = sets;
+            }
+            if let Some(active_set) = active_set {
+                self.active_set = active_set;
+            }
+        }
+
+        if let Some(images) = map.get("images") {
+            let err = || PropertyError {
+                key: "images".to_string(),
+                expected: "an array of images with ids",
+            };
+            for image in images.as_array().ok_or_else(err)? {
+                let id = string_field(image, "id").ok_or_else(err)?;
+                self.images.insert(id, image.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds a command by id, in the active set first
+    pub fn action(&self, id: &str) -> Option<&Node> {
+        fn find<'a>(nodes: &'a [Node], id: &str) -> Option<&'a Node> {
+            nodes.iter().find_map(|node| match node {
+                Node::Action { id: action, .. } if action == id => Some(node),
+                Node::Action { .. } => None,
+                Node::Category { nodes, .. } => find(nodes, id),
+            })
+        }
+
+        let active = self
+            .sets
+            .iter()
+            .filter(|set| Some(&set.id) == self.active_set.as_ref());
+        let others = self
+            .sets
+            .iter()
+            .filter(|set| Some(&set.id) != self.active_set.as_ref());
+        active.chain(others).find_map(|set| find(&set.nodes, id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn tree_and_active_set() {
+        let mut commands = Commands::default();
+        let update = json!({
+            "commands": {
+                "activeSet": "Part Studio",
+                "tree": { "nodes": [
+                    { "id": "Part Studio", "label": "Part Studio", "nodes": [
+                        { "id": "CAT_VIEW", "label": "View", "nodes": [
+                            { "id": "ID_FIT", "label": "Fit", "description": "Fit all" },
+                        ] },
+                        { "id": "ID_SKETCH", "type": 2 },
+                    ] },
+                    { "id": "Assembly", "label": "Assembly", "nodes": [] },
+                ] },
+            },
+            "images": [{ "id": "ID_FIT", "type": "image/png", "data": "iVBORw0" }],
+        });
+        commands.update(update.as_object().unwrap()).unwrap();
+
+        assert_eq!(commands.active_set.as_deref(), Some("Part Studio"));
+        assert_eq!(commands.sets.len(), 2);
+        assert_eq!(
+            commands.action("ID_SKETCH"),
+            Some(&Node::Action {
+                id: "ID_SKETCH".to_string(),
+                label: "ID_SKETCH".to_string(),
+                description: None,
+            })
+        );
+        assert!(commands.action("ID_FIT").is_some());
+        assert!(commands.action("CAT_VIEW").is_none());
+        assert!(commands.images.contains_key("ID_FIT"));
+
+        // Switching the set keeps the tree
+        let update = json!({ "commands": { "activeSet": "Assembly" } });
+        commands.update(update.as_object().unwrap()).unwrap();
+        assert_eq!(commands.active_set.as_deref(), Some("Assembly"));
+        assert_eq!(commands.sets.len(), 2);
+
+        let set = serde_json::to_value(&commands.sets[1]).unwrap();
+        assert_eq!(
+            set,
+            json!({ "id": "Assembly", "label": "Assembly", "nodes": [] })
+        );
+
+        let update = json!({ "commands": { "tree": [{ "id": "ID_FIT" }] } });
+        assert!(commands.update(update.as_object().unwrap()).is_err());
+        assert_eq!(commands.sets.len(), 2);
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    env, fmt, fs,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+use tracing::level_filters::LevelFilter;
+
+use crate::keys::ButtonMap;
+
+const APP_NAME: &str = "spacenav-web";
+
+/// Prefix of the environment variables overriding the config file
+const ENV_PREFIX: &str = "SPACENAV_WEB_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+impl LogLevel {
+    fn parse(s: &str) -> Option<LogLevel> {
+        Some(match s.to_ascii_lowercase().as_str() {
+            "error" => LogLevel::Error,
+            "warn" => LogLevel::Warn,
+            "info" => LogLevel::Info,
+            "debug" => LogLevel::Debug,
+            "trace" => LogLevel::Trace,
+            _ => return None,
+        })
+    }
+}
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> LevelFilter {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the TLS listener binds to. The 3Dconnexion SDK connects to
+    /// 127.51.68.120, so only change this together with a hosts entry.
+    pub bind: IpAddr,
+    pub port: u16,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Port returned by `/3dconnexion/nlproxy`, for setups where the listener
+    /// sits behind a forwarder. Defaults to `port`.
+    pub advertised_port: Option<u16>,
+    /// Port of an additional listener on 127.0.0.1 without TLS, for test
+    /// harnesses and tools which cannot deal with certificates
+    pub http_port: Option<u16>,
+    /// Protocol frames are logged at debug, navigation steps at trace
+    pub log_level: LogLevel,
+    /// JSON lines file receiving every WAMP frame of every session
+    pub trace_file: Option<PathBuf>,
+    /// Virtual key sent for each device button, by button number
+    pub buttons: ButtonMap,
+    /// Per-application profiles, reloaded when they change
+    pub profiles_dir: PathBuf,
+}
+impl Default for Config {
+    fn default() -> Config {
+        let data = data_dir();
+        Config {
+            bind: IpAddr::V4(Ipv4Addr::new(127, 51, 68, 120)),
+            port: 8181,
+            cert: data.join("server.crt"),
+            key: data.join("server.key"),
+            advertised_port: None,
+            http_port: None,
+            log_level: LogLevel::Info,
+            trace_file: None,
+            buttons: ButtonMap::default(),
+            profiles_dir: config_home().join("profiles"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    /// An environment override which does not parse, with the variable name
+    Env(String, String),
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "cannot read {}: {err}", path.display()),
+            ConfigError::Parse(path, err) => write!(f, "invalid config {}: {err}", path.display()),
+            ConfigError::Env(name, value) => write!(f, "invalid value for {name}: {value:?}"),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the file given with `--config`, or the first one found in the
+    /// XDG config directories, then applies the environment overrides.
+    /// Without any file the defaults are used.
+    pub fn load(explicit: Option<&Path>) -> Result<Config, ConfigError> {
+        let path = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            None => config_dirs()
+                .into_iter()
+                .flat_map(|dir| ["config.toml", "config.json"].map(|name| dir.join(name)))
+                .find(|path| path.is_file()),
+        };
+
+        let mut config = match path {
+            Some(path) => Config::from_file(&path)?,
+            None => Config::default(),
+        };
+        config.apply_env(|name| env::var(name).ok())?;
+        Ok(config)
+    }
+
+    /// Reads one config file, the format goes by its extension
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text =
+            fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+        parse_file(path, &text).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    /// Overrides settings from `SPACENAV_WEB_BIND`, `_PORT`, `_CERT`, `_KEY`,
+    /// `_ADVERTISED_PORT`, `_HTTP_PORT`, `_LOG`, `_TRACE_FILE` and
+    /// `_PROFILES_DIR`
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        fn parse<T: std::str::FromStr>(name: String, value: String) -> Result<T, ConfigError> {
+            value.parse().map_err(|_| ConfigError::Env(name, value))
+        }
+
+        let get = |key: &str| {
+            let name = format!("{ENV_PREFIX}{key}");
+            var(&name).map(|value| (name, value))
+        };
+        if let Some((name, value)) = get("BIND") {
+            self.bind = parse(name, value)?;
+        }
+        if let Some((name, value)) = get("PORT") {
+            self.port = parse(name, value)?;
+        }
+        if let Some((_, value)) = get("CERT") {
+            self.cert = PathBuf::from(value);
+        }
+        if let Some((_, value)) = get("KEY") {
+            self.key = PathBuf::from(value);
+        }
+        if let Some((name, value)) = get("ADVERTISED_PORT") {
+            self.advertised_port = Some(parse(name, value)?);
+        }
+        if let Some((name, value)) = get("HTTP_PORT") {
+            self.http_port = Some(parse(name, value)?);
+        }
+        if let Some((name, value)) = get("LOG") {
+            self.log_level = LogLevel::parse(&value).ok_or(ConfigError::Env(name, value))?;
+        }
+        if let Some((_, value)) = get("TRACE_FILE") {
+            self.trace_file = Some(PathBuf::from(value));
+        }
+        if let Some((_, value)) = get("PROFILES_DIR") {
+            self.profiles_dir = PathBuf::from(value);
+        }
+        Ok(())
+    }
+
+    /// Port the SDK should connect to
+    pub fn advertised_port(&self) -> u16 {
+        self.advertised_port.unwrap_or(self.port)
+    }
+}
+
+fn home_dir() -> PathBuf {
+    env::var_os("HOME").map(PathBuf::from).unwrap_or_default()
+}
+
+/// Reads an XDG base directory variable, which must be absolute to count
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| home_dir().join(fallback))
+}
+
+/// `$XDG_CONFIG_HOME/spacenav-web`
+fn config_home() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join(APP_NAME)
+}
+
+/// `$XDG_CONFIG_HOME/spacenav-web` followed by each of `$XDG_CONFIG_DIRS`
+pub fn config_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![config_home()];
+    let system = env::var("XDG_CONFIG_DIRS").unwrap_or_default();
+    let system = if system.is_empty() {
+        "/etc/xdg"
+    } else {
+        &system
+    };
+    dirs.extend(
+        system
+            .split(':')
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .map(|dir| dir.join(APP_NAME)),
+    );
+    dirs
+}
+
+/// `$XDG_DATA_HOME/spacenav-web`, where generated certificates live
+pub fn data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", ".local/share").join(APP_NAME)
+}
+
+/// Parses JSON if the file ends in `.json`, TOML otherwise. Shared with
+/// the profiles, which are written the same way.
+pub fn parse_file<T: DeserializeOwned>(path: &Path, text: &str) -> Result<T, String> {
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(text).map_err(|err| err.to_string())
+    } else {
+        toml::from_str(text).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::keys::{Binding, VirtualKey};
+
+    #[test]
+    fn toml_and_json() {
+        let dir = env::temp_dir().join(format!("spacenav-web-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let toml_path = dir.join("config.toml");
+        fs::write(
+            &toml_path,
+            "port = 9000\ncert = \"/etc/ssl/nl.crt\"\nlog_level = \"debug\"\n\n[buttons]\n0 = \"fit\"\n",
+        )
+        .unwrap();
+        let config = Config::from_file(&toml_path).unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.advertised_port(), 9000);
+        assert_eq!(config.cert, PathBuf::from("/etc/ssl/nl.crt"));
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.bind, Config::default().bind);
+        assert_eq!(config.buttons.get(0), Some(&Binding::Key(VirtualKey(2))));
+        assert_eq!(config.buttons.get(1), None);
+
+        let json_path = dir.join("config.json");
+        fs::write(&json_path, r#"{"bind":"127.0.0.1","advertised_port":8181}"#).unwrap();
+        let config = Config::from_file(&json_path).unwrap();
+        assert_eq!(config.bind, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(config.advertised_port(), 8181);
+        assert_eq!(config.buttons, ButtonMap::default());
+
+        fs::write(&toml_path, "prot = 9000\n").unwrap();
+        assert!(matches!(
+            Config::from_file(&toml_path),
+            Err(ConfigError::Parse(..))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn env_overrides() {
+        let vars: HashMap<&str, &str> = [
+            ("SPACENAV_WEB_PORT", "8443"),
+            ("SPACENAV_WEB_KEY", "/tmp/nl.key"),
+            ("SPACENAV_WEB_HTTP_PORT", "8180"),
+            ("SPACENAV_WEB_LOG", "WARN"),
+            ("SPACENAV_WEB_PROFILES_DIR", "/tmp/profiles"),
+        ]
+        .into();
+        let mut config = Config::default();
+        config
+            .apply_env(|name| vars.get(name).map(|v| v.to_string()))
+            .unwrap();
+        assert_eq!(config.port, 8443);
+        assert_eq!(config.key, PathBuf::from("/tmp/nl.key"));
+        assert_eq!(config.http_port, Some(8180));
+        assert_eq!(config.log_level, LogLevel::Warn);
+        assert_eq!(config.profiles_dir, PathBuf::from("/tmp/profiles"));
+
+        let err = config
+            .apply_env(|name| (name == "SPACENAV_WEB_BIND").then(|| "localhost".to_string()))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value for SPACENAV_WEB_BIND: \"localhost\""
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use std::{io, path::Path};
+
+/// Reads a file of JSON lines, skipping blank ones and reporting the first
+/// bad line by number
+pub fn load<T>(path: &Path, parse_line: impl Fn(&str) -> Result<T, String>) -> io::Result<Vec<T>> {
+    std::fs::read_to_string(path)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| {
+            parse_line(line).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {err}", path.display(), n + 1),
+                )
+            })
+        })
+        .collect()
+}
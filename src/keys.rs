@@ -0,0 +1,202 @@
+use std::{collections::HashMap, fmt};
+
+use serde::{de, de::IntoDeserializer, Deserialize, Deserializer};
+
+use crate::navigation::Mode;
+
+/// Virtual key ids of the 3Dconnexion SDK (`V3DK_*`), which the client knows
+/// regardless of the device the key is on
+const KEY_NAMES: [(&str, u32); 37] = [
+    ("menu", 1),
+    ("fit", 2),
+    ("top", 3),
+    ("left", 4),
+    ("right", 5),
+    ("front", 6),
+    ("bottom", 7),
+    ("back", 8),
+    ("roll_cw", 9),
+    ("roll_ccw", 10),
+    ("iso1", 11),
+    ("iso2", 12),
+    ("1", 13),
+    ("2", 14),
+    ("3", 15),
+    ("4", 16),
+    ("5", 17),
+    ("6", 18),
+    ("7", 19),
+    ("8", 20),
+    ("9", 21),
+    ("10", 22),
+    ("esc", 23),
+    ("alt", 24),
+    ("shift", 25),
+    ("ctrl", 26),
+    ("rotate", 27),
+    ("panzoom", 28),
+    ("dominant", 29),
+    ("plus", 30),
+    ("minus", 31),
+    ("spin_cw", 32),
+    ("spin_ccw", 33),
+    ("tilt_cw", 34),
+    ("tilt_ccw", 35),
+    ("enter", 36),
+    ("delete", 37),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualKey(pub u32);
+impl VirtualKey {
+    pub fn from_name(name: &str) -> Option<VirtualKey> {
+        let name = name.to_ascii_lowercase().replace('-', "_");
+        KEY_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, id)| VirtualKey(*id))
+    }
+}
+
+/// What a device button does: send a virtual key, run one of the
+/// application's commands by id or change the navigation mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    Key(VirtualKey),
+    Command(String),
+    Mode(Mode),
+    /// Cycles through the navigation modes
+    NextMode,
+}
+
+/// Keys are given by name, e.g. `"fit"`, or by id for keys without one.
+/// Commands and modes are tables: `{ command = "ID_OPEN" }`,
+/// `{ mode = "fly" }` or `{ mode = "next" }`.
+impl<'de> Deserialize<'de> for Binding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Binding, D::Error> {
+        struct BindingVisitor;
+        impl<'de> de::Visitor<'de> for BindingVisitor {
+            type Value = Binding;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a 3Dconnexion key name or id, a command or a mode")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Binding, E> {
+                VirtualKey::from_name(name)
+                    .map(Binding::Key)
+                    .ok_or_else(|| E::custom(format!("unknown key name {name:?}")))
+            }
+
+            fn visit_i64<E: de::Error>(self, id: i64) -> Result<Binding, E> {
+                u32::try_from(id)
+                    .map(|id| Binding::Key(VirtualKey(id)))
+                    .map_err(|_| E::custom(format!("invalid key id {id}")))
+            }
+
+            fn visit_u64<E: de::Error>(self, id: u64) -> Result<Binding, E> {
+                self.visit_i64(id as i64)
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Binding, A::Error> {
+                let binding = match map.next_key::<String>()?.as_deref() {
+                    Some("command") => Binding::Command(map.next_value()?),
+                    Some("mode") => match map.next_value::<String>()?.as_str() {
+                        "next" => Binding::NextMode,
+                        mode => {
+                            let mode: de::value::StrDeserializer<A::Error> =
+                                mode.into_deserializer();
+                            Binding::Mode(Mode::deserialize(mode)?)
+                        }
+                    },
+                    Some(key) => return Err(de::Error::unknown_field(key, &["command", "mode"])),
+                    None => return Err(de::Error::missing_field("command")),
+                };
+                if let Some(key) = map.next_key::<String>()? {
+                    return Err(de::Error::custom(format!(
+                        "unexpected {key:?}, a button does one thing"
+                    )));
+                }
+                Ok(binding)
+            }
+        }
+        deserializer.deserialize_any(BindingVisitor)
+    }
+}
+
+/// What each device button does, by button number
+#[derive(Debug, Clone, PartialEq)]
+pub struct ButtonMap {
+    bindings: HashMap<i32, Binding>,
+}
+impl ButtonMap {
+    pub fn empty() -> ButtonMap {
+        ButtonMap {
+            bindings: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, bnum: i32) -> Option<&Binding> {
+        self.bindings.get(&bnum)
+    }
+}
+
+/// The two buttons of a SpaceMouse Compact or Wireless, as 3DxWare sets them up
+impl Default for ButtonMap {
+    fn default() -> ButtonMap {
+        ButtonMap {
+            bindings: HashMap::from([
+                (0, Binding::Key(VirtualKey(1))),
+                (1, Binding::Key(VirtualKey(2))),
+            ]),
+        }
+    }
+}
+
+/// Config files use the button numbers as keys, which are always strings
+/// in TOML: `[buttons]` followed by `0 = "menu"`
+impl<'de> Deserialize<'de> for ButtonMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ButtonMap, D::Error> {
+        let raw = HashMap::<String, Binding>::deserialize(deserializer)?;
+        let bindings = raw
+            .into_iter()
+            .map(|(bnum, binding)| {
+                bnum.parse()
+                    .map(|bnum| (bnum, binding))
+                    .map_err(|_| de::Error::custom(format!("invalid button number {bnum:?}")))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ButtonMap { bindings })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_map() {
+        let map: ButtonMap = toml::from_str(
+            "0 = \"Fit\"\n1 = \"roll-cw\"\n12 = 13\n3 = { command = \"ID_OPEN\" }\n\
+             4 = { mode = \"orbit\" }\n5 = { mode = \"next\" }\n",
+        )
+        .unwrap();
+        assert_eq!(map.get(0), Some(&Binding::Key(VirtualKey(2))));
+        assert_eq!(map.get(1), Some(&Binding::Key(VirtualKey(9))));
+        assert_eq!(map.get(12), Some(&Binding::Key(VirtualKey(13))));
+        assert_eq!(map.get(3), Some(&Binding::Command("ID_OPEN".to_string())));
+        assert_eq!(map.get(4), Some(&Binding::Mode(Mode::Target)));
+        assert_eq!(map.get(5), Some(&Binding::NextMode));
+        assert_eq!(map.get(2), None);
+
+        assert!(toml::from_str::<ButtonMap>("0 = \"warp\"\n").is_err());
+        assert!(toml::from_str::<ButtonMap>("left = \"fit\"\n").is_err());
+        assert!(serde_json::from_str::<ButtonMap>(r#"{"0":-1}"#).is_err());
+        assert!(serde_json::from_str::<ButtonMap>(r#"{"0":{"cmd":"ID_OPEN"}}"#).is_err());
+        assert!(serde_json::from_str::<ButtonMap>(r#"{"0":{"mode":"walk"}}"#).is_err());
+        assert!(
+            serde_json::from_str::<ButtonMap>(r#"{"0":{"mode":"fly","command":"ID_OPEN"}}"#)
+                .is_err()
+        );
+    }
+}
@@ -1,510 +1,378 @@
-use std::{collections::HashMap, ops::IndexMut, string};
-
-use futures_util::{
-    stream::{SplitSink, SplitStream},
-    FutureExt, SinkExt, StreamExt,
-};
-use matrix::Matrix;
-use spnav_posrot::Position;
-use warp::{
-    ws::{Message, WebSocket},
-    Filter,
+use std::{net::Ipv4Addr, path::PathBuf, process::ExitCode, sync::Arc};
+
+use clap::{Parser, Subcommand};
+use config::Config;
+use registry::Registry;
+use session::ServerState;
+use tokio::{
+    sync::{broadcast, watch},
+    time::Instant,
 };
+use tracing::level_filters::LevelFilter;
 
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use serde_json::{self, Number, Value};
-
-use crate::matrix::MatrixOperationable;
 
+mod admin;
+mod cert;
+mod client_replay;
+mod commands;
+mod config;
+mod json_lines;
+mod keys;
 mod matrix;
-mod quat;
-mod spnav_posrot;
+mod motion;
+mod motion_file;
+mod navigation;
+mod profile;
+mod properties;
+mod registry;
+mod rpc;
+mod server;
+mod session;
+mod spnav;
+#[cfg(test)]
+mod test_client;
+mod trace;
 mod vector;
-
-#[tokio::main]
-async fn main() {
-    // TEST
-    let mut pos: Position = Position {
-        pos: [
-            0.06597165763378143,
-            -0.11405778676271439,
-            0.07619363069534302,
-        ],
-        rot: [0.0, 0.0, 0.0, 1.0],
-    };
-
-    let mut view: Matrix = [
-        0.8660254057273917,
-        0.4999999966347066,
-        6.730586865622001e-9,
-        0.0,
-        -0.25000000420661683,
-        0.43301270140648107,
-        0.8660254028129621,
-        0.0,
-        0.43301269557762184,
-        -0.75000000252397,
-        0.5000000016826467,
-        0.0,
-        0.06750000169786065,
-        -0.11691343455006951,
-        0.07794228970004634,
-        1.0,
-    ];
-
-    println!("OLD:  {:?}", view);
-    view.view(&pos);
-    println!("NEW:  {:?}", view);
-
-    return;
-    // TEST
-
-    let websocket = warp::path::end()
-        .and(warp::ws())
-        .map(|ws: warp::ws::Ws| {
-            // This will call our function if the handshake succeeds.
-            ws.on_upgrade(move |socket| {
-                // let (tx, rx) = socket.split();
-                // // rx.forward(tx).map(|result| {
-                // //     if let Err(e) = result {
-                // //         eprintln!("websocket error: {:?}", e);
-                // //     }
-                // // })
-                // send_welcome(&tx);
-
-                // rx.forward(sink)
-                handle_session(socket)
-            })
-        })
-        .with(warp::reply::with::header("Sec-WebSocket-Protocol", "wamp"));
-
-    // GET / -> index html
-    let proxy = warp::path!("3dconnexion" / "nlproxy").map(|| "{\"port\":8181}");
-
-    let routes = proxy.or(websocket).with(warp::reply::with::header(
-        "Access-Control-Allow-Origin",
-        "*",
-    ));
-
-    warp::serve(routes)
-        .tls()
-        .cert_path("C:\\Program Files (x86)\\3Dconnexion\\3DxWare\\3DxNLServer\\bin\\server.crt")
-        .key_path("C:\\Program Files (x86)\\3Dconnexion\\3DxWare\\3DxNLServer\\bin\\server.key")
-        .run(([127, 51, 68, 120], 8181))
-        .await;
-}
-
-async fn send_welcome(tx: &mut SplitSink<WebSocket, Message>) {
-    tx.send(Message::text(format!("[0,{:?},1,\"Nl-Proxy v1.4.3.19386 Copyright 2013-2022 3Dconnexion. All rights reserved.\"]", "8GXm6SS4smp3Ai0e"))).await.unwrap();
+mod wamp;
+
+/// 3Dconnexion NL-Proxy for browser CAD applications, backed by spacenavd
+#[derive(Parser)]
+#[command(name = "spacenav-web", version)]
+struct Cli {
+    /// Config file to use instead of the one in the XDG config directories
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-enum ClientReturnHandlers {
-    SelectionEmpty,
-    ViewPerspective,
-    ViewAffine,
-    ViewTarget,
+#[derive(Subcommand)]
+enum Command {
+    /// Run the proxy, the default
+    Serve {
+        /// Record every WAMP frame to this file, overriding `trace_file`
+        #[arg(long)]
+        record: Option<PathBuf>,
+    },
+    /// Run the proxy with device events from a file written by `monitor`
+    Replay {
+        file: PathBuf,
+        /// Start over at the end of the file
+        #[arg(long)]
+        repeat: bool,
+        /// Record every WAMP frame to this file, overriding `trace_file`
+        #[arg(long)]
+        record: Option<PathBuf>,
+    },
+    /// Act as the browser of a recorded session against a running proxy and
+    /// report where the proxy behaves differently
+    ReplayClient {
+        /// Trace written with `--record` or `trace_file`
+        trace: PathBuf,
+        /// Session to replay when the trace holds several
+        #[arg(long)]
+        instance: Option<u32>,
+        /// Websocket URL of the proxy, defaults to the plain `http_port`
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// Print device events as JSON lines, the format `replay` reads
+    Monitor,
+    /// Manage the TLS certificate
+    Cert {
+        #[command(subcommand)]
+        command: CertCommand,
+    },
+    /// Check the connection to spacenavd and the TLS certificate
+    Check,
 }
 
-struct spnav_event_motion {
-    event_type: i32,
-    x: i32,
-    y: i32,
-    z: i32,
-    rx: i32,
-    ry: i32,
-    rz: i32,
-    period: u32,
-    // int *data;
+#[derive(Subcommand)]
+enum CertCommand {
+    /// Create a local CA and a server certificate signed by it
+    Generate {
+        /// Trust the CA in the NSS databases of Chromium and Firefox
+        #[arg(long)]
+        install: bool,
+    },
 }
 
-struct Session {
-    transmitter: SplitSink<WebSocket, Message>,
-    receiver: SplitStream<WebSocket>,
-    instance: u32,
-    callbacks: HashMap<String, ClientReturnHandlers>,
-    position: Position,
-    view_matrix: Matrix,
-    transactions: u32,
-}
-impl Session {
-    fn new(socket: WebSocket) -> Session {
-        let (session_tx, session_rx) = socket.split();
-        Session {
-            transmitter: session_tx,
-            receiver: session_rx,
-            instance: thread_rng().gen(),
-            callbacks: HashMap::new(),
-            position: Position::new(),
-            view_matrix: [0.0; 16],
-            transactions: 1,
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
         }
-    }
-}
-
-async fn handle_session(socket: WebSocket) {
-    println!("NEW SESSION");
-
-    let mut session = Session::new(socket);
-
-    send_welcome(&mut session.transmitter).await;
-
-    while let Some(result) = session.receiver.next().await {
-        let msg = match result {
-            Ok(msg) => msg,
-            Err(e) => {
-                println!("WS ERROR: {e}");
-                break;
-            }
-        };
-        handle_msg(msg, &mut session).await;
-    }
-}
-
-fn parse_msg<'a>(msg: Message) -> Result<(MessageType, Value), ()> {
-    // Parse as json
-    let json: Value = serde_json::from_slice(msg.as_bytes()).map_err(|err| ())?;
-
-    // Expect array
-    let json_array = json.as_array().ok_or(())?;
-
-    // Expect length
-    match json_array.len() {
-        2..=6 => (),
-        _ => return Err(()),
     };
-
-    // Parse MessageType
-    let msgType: MessageType = match &json_array[0] {
-        Value::Number(msgType) => match msgType.as_u64() {
-            Some(msgType) if msgType <= 8 => MessageType::from_u64(msgType),
-            _ => return Err(()),
-        },
-        _ => return Err(()),
-    };
-
-    Ok((msgType, json))
-}
-
-async fn handle_msg(msg: Message, session: &mut Session) {
-    let mut msg_text = msg.to_str().unwrap().to_string();
-    msg_text.truncate(60);
-    println!("MESSAGE: {:?}", msg_text);
-
-    let (msgType, msg) = match parse_msg(msg) {
-        Ok((msgType, json)) => (msgType, json),
-        Err(_) => return,
-    };
-
-    let json = msg.as_array().expect("Unwrapping is handled in parse_msg");
-
-    let ret = match msgType {
-        MessageType::Welcome => return, // Server
-        MessageType::Prefix => return,  // Client: Can be ignored
-        MessageType::Call => handle_call(json, session).await,
-        MessageType::CallResult => {
-            println!("CallResult: {:?}", json);
-            let callback_id = match json[1].as_str() {
-                Some(callback_id) => callback_id,
-                None => return,
-            };
-            let return_handler = match session.callbacks.remove(callback_id) {
-                Some(return_handler) => return_handler,
-                None => return,
-            };
-            match return_handler {
-                ClientReturnHandlers::SelectionEmpty => todo!(),
-                ClientReturnHandlers::ViewPerspective => todo!(),
-                ClientReturnHandlers::ViewAffine => {
-                    let view_affine = json[2].as_array().unwrap();
-                    for (i, v) in session.view_matrix.iter_mut().enumerate() {
-                        *v = view_affine[i].as_f64().unwrap() as f32;
-                    }
-
-                    let id = generate_id();
-                    session
-                        .callbacks
-                        .insert(id.clone(), ClientReturnHandlers::ViewTarget);
-                    session
-                        .transmitter
-                        .send(build_read_call(session.instance, &id, "view.target"))
-                        .await;
-                }
-                ClientReturnHandlers::ViewTarget => {
-                    let view_target = json[2].as_array().unwrap();
-                    for (i, v) in session.position.pos.iter_mut().enumerate() {
-                        *v = view_target[i].as_f64().unwrap() as f32;
-                    }
-                    session
-                        .transmitter
-                        .send(build_update_call(
-                            session.instance,
-                            &generate_id(),
-                            "motion",
-                            "true",
-                        ))
-                        .await;
+    tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::from(config.log_level))
+        .with_writer(std::io::stderr)
+        .init();
+    tracing::debug!(?config, "loaded config");
+
+    match cli.command.unwrap_or(Command::Serve { record: None }) {
+        Command::Serve { record } => {
+            config.trace_file = record.or(config.trace_file);
+            let (device_tx, _) = broadcast::channel(64);
+            tokio::spawn(spnav::run(
+                PathBuf::from(spnav::SPNAV_SOCK_PATH),
+                device_tx.clone(),
+            ));
+            serve(&config, device_tx).await
+        }
+        Command::Replay {
+            file,
+            repeat,
+            record,
+        } => {
+            config.trace_file = record.or(config.trace_file);
+            let records = match motion_file::load(&file) {
+                Ok(records) => records,
+                Err(err) => {
+                    eprintln!("{err}");
+                    return ExitCode::FAILURE;
                 }
             };
-            return;
+            let (device_tx, _) = broadcast::channel(64);
+            tokio::spawn(motion_file::replay(records, device_tx.clone(), repeat));
+            serve(&config, device_tx).await
         }
-        MessageType::CallError => {
-            println!("CallError: {:?}", json);
-            return;
+        Command::ReplayClient {
+            trace,
+            instance,
+            url,
+        } => {
+            let url = match (url, config.http_port) {
+                (Some(url), _) => url,
+                (None, Some(port)) => format!("ws://127.0.0.1:{port}/"),
+                (None, None) => {
+                    eprintln!("Set http_port in the config or pass --url");
+                    return ExitCode::FAILURE;
+                }
+            };
+            replay_client(&trace, instance, &url).await
         }
-        MessageType::Subscribe => {
-            println!("Subscribe: {:?}", json);
-
-            // Init variables
-            // tx.send(build_update_call(
-            //     instance,
-            //     &generate_id(),
-            //     "self:read",
-            //     "selection.empty",
-            // ))
-            // .await;
-
-            // tx.send(build_update_call(
-            //     instance,
-            //     &generate_id(),
-            //     "self:read",
-            //     "view.perspective",
-            // ))
-            // .await;
-
-            // tx.send(build_update_call(
-            //     instance,
-            //     &generate_id(),
-            //     "self:read",
-            //     "view.affine",
-            // ))
-            // .await;
-
-            // tx.send(build_update_call(
-            //     instance,
-            //     &generate_id(),
-            //     "self:read",
-            //     "view.target",
-            // ))
-            // .await;
-
-            // [8,"3dconnexion:3dcontroller/6884113743086",[2,"t3bLavZGeDHtHSly","self:update","","motion",true]]
-
-            let id = generate_id();
-            session
-                .callbacks
-                .insert(id.clone(), ClientReturnHandlers::ViewAffine);
-
-            session
-                .transmitter
-                .send(build_read_call(session.instance, &id, "view.affine"))
-                .await;
+        Command::Monitor => monitor().await,
+        Command::Cert {
+            command: CertCommand::Generate { install },
+        } => generate_cert(&config, install),
+        Command::Check => check(&config).await,
+    }
+}
 
-            return;
+/// Serves the NL-Proxy routes over TLS, and without on the optional plain
+/// port, with device events taken from `device_tx`
+async fn serve(config: &Config, device_tx: broadcast::Sender<spnav::spnav_event>) -> ExitCode {
+    let tls_usable = match cert::check_files(&config.cert, &config.key) {
+        Ok(()) => true,
+        // Browsers reaching the plain listener do not need a certificate
+        Err(err) if config.http_port.is_some() => {
+            tracing::warn!(%err, "no usable certificate, serving plain HTTP only");
+            false
         }
-        MessageType::Unsubscribe => {
-            println!("Unsubscribe: {:?}", json);
-            return;
+        Err(err) => {
+            tracing::error!(%err, "no usable certificate, run `spacenav-web cert generate`");
+            return ExitCode::FAILURE;
         }
-        MessageType::Publish => {
-            println!("Publish: {:?}", json);
-            return;
-        }
-        MessageType::Event => return, // Server
     };
+    if tls_usable {
+        cert::check_expiry(&config.cert);
+    }
 
-    let success = match ret {
-        Ok(ret) => session.transmitter.send(ret).await,
-        Err(_) => return,
+    let trace = match &config.trace_file {
+        Some(path) => match trace::FrameTrace::open(path) {
+            Ok(trace) => Some(trace),
+            Err(err) => {
+                tracing::error!(path = %path.display(), %err, "cannot open WAMP trace");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
     };
-
-    match success {
-        Ok(_) => return,
-        Err(_) => println!("ERROR WHILE SENDING"),
+    let profiles = profile::load_dir(&config.profiles_dir);
+    tracing::info!(dir = %config.profiles_dir.display(), count = profiles.len(), "profiles loaded");
+    let (profiles_tx, profiles_rx) = watch::channel(Arc::new(profiles));
+    tokio::spawn(profile::watch(config.profiles_dir.clone(), profiles_tx));
+
+    let state = ServerState {
+        registry: Registry::new(),
+        trace,
+        buttons: Arc::new(config.buttons.clone()),
+        profiles: profiles_rx,
     };
+    tokio::spawn(state.registry.clone().route(device_tx.subscribe()));
+    let routes = server::routes(config.advertised_port(), state);
+
+    let tls = tls_usable.then(|| {
+        warp::serve(routes.clone())
+            .tls()
+            .cert_path(&config.cert)
+            .key_path(&config.key)
+            .run((config.bind, config.port))
+    });
+    match (tls, config.http_port) {
+        (Some(tls), Some(port)) => {
+            let plain = warp::serve(routes).run((Ipv4Addr::LOCALHOST, port));
+            tokio::join!(tls, plain);
+        }
+        (Some(tls), None) => tls.await,
+        (None, Some(port)) => warp::serve(routes).run((Ipv4Addr::LOCALHOST, port)).await,
+        (None, None) => unreachable!("serving without TLS needs http_port"),
+    }
+    ExitCode::SUCCESS
 }
 
-/*
-[2,"0.wu6w9bnqe4","3dx_rpc:create","3dconnexion:3dmouse","0.6.0"]
-[2,"0.h2jd78cvemc","3dx_rpc:update","3dconnexion:3dcontroller/6884113743086",{"commands":{"activeSet":"Part Studio"}}]
-[2,"0.pim5f32a7ff","3dx_rpc:update","3dconnexion:3dcontroller/6884113743086",{"focus":true}]
- */
-async fn handle_call(json: &Vec<Value>, session: &mut Session) -> Result<Message, ()> {
-    // if (json.len() !== )
-
-    let msg_id = json[1].as_str().ok_or(())?;
-
-    let fn_name: &str = json[2].as_str().ok_or(())?;
-
-    Ok(match fn_name {
-        "3dx_rpc:create" => match json[3].as_str().ok_or(())? {
-            "3dconnexion:3dmouse" => {
-                build_result(msg_id, format!("{{\"connexion\":\"{}\"}}", generate_id()))
-            }
-            "3dconnexion:3dcontroller" => {
-                build_result(msg_id, format!("{{\"instance\":{}}}", session.instance))
+/// `monitor`: prints every device event until spacenavd goes away
+async fn monitor() -> ExitCode {
+    let mut conn = match spnav::Connection::connect(spnav::SPNAV_SOCK_PATH.as_ref()).await {
+        Ok(conn) => conn,
+        Err(err) => {
+            eprintln!(
+                "Cannot connect to spacenavd at {}: {err}",
+                spnav::SPNAV_SOCK_PATH
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("Connected to spacenavd, protocol v{}", conn.protocol());
+
+    let start = Instant::now();
+    loop {
+        match conn.next_event().await {
+            Ok(event) => {
+                let time = start.elapsed().as_millis() as u64;
+                println!("{}", motion_file::to_line(time, &event));
             }
-            _ => {
-                println!("UNHANDLED CALL: {:?}", json);
-                return Err(());
+            Err(err) => {
+                eprintln!("spacenavd: {err}");
+                return ExitCode::FAILURE;
             }
-        },
-        "3dx_rpc:update" => {
-            println!("UPDATE:");
-            if let Value::Object(map) = &json[4] {
-                if let Some((_, Value::Object(frame))) = map.get_key_value("frame") {
-                    if let Some((_, Value::Number(time))) = frame.get_key_value("time") {
-                        std::thread::sleep(std::time::Duration::from_secs(5));
-
-                        session
-                            .transmitter
-                            .send(build_update_call(
-                                session.instance,
-                                &generate_id(),
-                                "transaction",
-                                &session.transactions.to_string(),
-                            ))
-                            .await;
-
-                        session.transactions += 1;
-
-                        println!("OLD:  {:?}", session.position);
-
-                        session.position.move_view(&spnav_event_motion {
-                            event_type: 0,
-                            x: 0,
-                            y: 0,
-                            z: 0,
-                            rx: 0,
-                            ry: 0,
-                            rz: 0,
-                            period: 0,
-                        });
-
-                        println!("NEW:  {:?}", session.position);
-
-                        println!("OLD:  {:?}", session.view_matrix);
-
-                        session.view_matrix.view(&session.position);
-
-                        println!("NEW:  {:?}", session.view_matrix);
+        }
+    }
+}
 
-                        session
-                            .transmitter
-                            .send(build_update_call(
-                                session.instance,
-                                &generate_id(),
-                                "view.affine",
-                                &format!("{:?}", session.view_matrix),
-                            ))
-                            .await;
+/// `replay-client`: replays one session of a trace and prints the report
+async fn replay_client(path: &std::path::Path, instance: Option<u32>, url: &str) -> ExitCode {
+    let entries = match trace::load(path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match client_replay::session_frames(&entries, instance) {
+        Ok(frames) => client_replay::replay(&frames, url).await,
+        Err(err) => Err(err),
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-                        session
-                            .transmitter
-                            .send(build_update_call(
-                                session.instance,
-                                &generate_id(),
-                                "transaction",
-                                "0",
-                            ))
-                            .await;
+    println!("Sent {} frames", report.sent);
+    for difference in &report.call_differences {
+        println!("note: {difference}");
+    }
+    for mismatch in &report.mismatches {
+        println!("MISMATCH: {mismatch}");
+    }
+    if report.mismatches.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
 
-                        session
-                            .transmitter
-                            .send(build_update_call(
-                                session.instance,
-                                &generate_id(),
-                                "motion",
-                                "false",
-                            ))
-                            .await;
-                    }
-                }
-            }
+/// `check`: reports whether `serve` would find spacenavd and a usable
+/// certificate
+async fn check(config: &Config) -> ExitCode {
+    let mut ok = true;
+
+    match spnav::Connection::connect(spnav::SPNAV_SOCK_PATH.as_ref()).await {
+        Ok(conn) => println!("spacenavd: ok, protocol v{}", conn.protocol()),
+        Err(err) => {
+            println!(
+                "spacenavd: cannot connect to {}: {err}",
+                spnav::SPNAV_SOCK_PATH
+            );
+            ok = false;
+        }
+    }
 
-            build_result(msg_id, "{}".to_string())
+    // The same check serve runs before listening
+    match cert::check_files(&config.cert, &config.key)
+        .and_then(|()| cert::seconds_left(&config.cert))
+    {
+        Ok(left) if left <= 0 => {
+            println!("certificate: {} has expired", config.cert.display());
+            ok = false;
         }
-        _ => {
-            println!("UNHANDLED CALL: {:?}", json);
-            return Err(());
+        Ok(left) => println!(
+            "certificate: ok, {} expires in {} days",
+            config.cert.display(),
+            left / 86400
+        ),
+        Err(err) => {
+            println!("certificate: {err}");
+            ok = false;
         }
-    })
-}
+    }
 
-/*
-{
-  "_version": "0.8.2.1",
-  "browserNotSupportedMessage": "Browser does not support WebSockets (RFC6455)",
-  "_idchars": "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
-  "_idlen": 16,
-  "_subprotocol": "wamp",
-  "_debugrpc": false,
-  "_debugpubsub": false,
-  "_debugws": false,
-  "_debugconnect": false,
-  "_MESSAGE_TYPEID_WELCOME": 0,
-  "_MESSAGE_TYPEID_PREFIX": 1,
-  "_MESSAGE_TYPEID_CALL": 2,
-  "_MESSAGE_TYPEID_CALL_RESULT": 3,
-  "_MESSAGE_TYPEID_CALL_ERROR": 4,
-  "_MESSAGE_TYPEID_SUBSCRIBE": 5,
-  "_MESSAGE_TYPEID_UNSUBSCRIBE": 6,
-  "_MESSAGE_TYPEID_PUBLISH": 7,
-  "_MESSAGE_TYPEID_EVENT": 8,
-  "CONNECTION_CLOSED": 0,
-  "CONNECTION_LOST": 1,
-  "CONNECTION_RETRIES_EXCEEDED": 2,
-  "CONNECTION_UNREACHABLE": 3,
-  "CONNECTION_UNSUPPORTED": 4,
-  "CONNECTION_UNREACHABLE_SCHEDULED_RECONNECT": 5,
-  "CONNECTION_LOST_SCHEDULED_RECONNECT": 6,
-  "_UA_FIREFOX": {},
-  "_UA_CHROME": {},
-  "_UA_CHROMEFRAME": {},
-  "_UA_WEBKIT": {},
-  "_UA_WEBOS": {}
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
- */
 
-#[derive(Debug)]
-enum MessageType {
-    /// 0: Server greets client
-    Welcome,
-    /// 1: Client greets server
-    Prefix,
-    /// 2: Client calls server function
-    Call,
-    /// 3: Return of server/client function
-    CallResult,
-    /// 4: Client sends event notice to server
-    CallError,
-    /// 5: Client joined room specified by server
-    Subscribe,
-    /// 6
-    Unsubscribe,
-    /// 7
-    Publish,
-    /// 8: Server calls client function
-    Event,
-}
-impl MessageType {
-    fn from_u64(value: u64) -> MessageType {
-        match value {
-            0 => MessageType::Welcome,
-            1 => MessageType::Prefix,
-            2 => MessageType::Call,
-            3 => MessageType::CallResult,
-            4 => MessageType::CallError,
-            5 => MessageType::Subscribe,
-            6 => MessageType::Unsubscribe,
-            7 => MessageType::Publish,
-            8 => MessageType::Event,
-            _ => {
-                println!("UNEXPECTED MESSAGE TYPE: {value}");
-                MessageType::Welcome
+/// `cert generate`: creates the CA and server certificate, then installs the
+/// CA into the browsers' NSS databases or explains how to
+fn generate_cert(config: &Config, install: bool) -> ExitCode {
+    let paths = cert::CertPaths::new(&config::data_dir(), &config.cert, &config.key);
+    let created = match cert::generate(&paths) {
+        Ok(created) => created,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("Wrote {} and {}", paths.cert.display(), paths.key.display());
+    if created {
+        println!("Created CA {}", paths.ca_cert.display());
+    }
+
+    if !install {
+        println!("{}", cert::instructions(&paths.ca_cert));
+        return ExitCode::SUCCESS;
+    }
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    let dbs = cert::nss_databases(&home);
+    if dbs.is_empty() {
+        println!("No NSS database found");
+        println!("{}", cert::instructions(&paths.ca_cert));
+        return ExitCode::FAILURE;
+    }
+    let mut code = ExitCode::SUCCESS;
+    for db in dbs {
+        match cert::install_nss(&db, &paths.ca_cert) {
+            Ok(()) => println!("Installed CA into {}", db.display()),
+            Err(err) => {
+                eprintln!("Cannot install CA into {}: {err}", db.display());
+                code = ExitCode::FAILURE;
             }
         }
     }
+    code
 }
 
 /*
@@ -517,61 +385,3 @@ fn generate_id() -> String {
         .map(char::from)
         .collect()
 }
-
-/*
-[0,"8GXm6SS4smp3Ai0e",1,"Nl-Proxy v1.4.3.19386 Copyright 2013-2022 3Dconnexion. All rights reserved."]
- */
-fn build_welcome(id: &str) -> Message {
-    Message::text(format!(
-        "[{},{},1,\"Spacenav Proxy\"]",
-        MessageType::Welcome as u32,
-        id
-    ))
-}
-
-/*
-[3,"0.pim5f32a7ff",{}]
- */
-fn build_result(id: &str, data: String) -> Message {
-    Message::text(format!(
-        "[{},\"{}\",{}]",
-        MessageType::CallResult as u32,
-        id,
-        data
-    ))
-}
-
-/*
-[8,"3dconnexion:3dcontroller/6884113743086",[2,"1cJSqNoRqbVxr4Ds","self:update","","hit.selectionOnly",false]]
-[8,"3dconnexion:3dcontroller/6884113743086",[2,"DMr5ZjiANwTtk65Y","self:update","","settings.changed",2]]
-[8,"3dconnexion:3dcontroller/6884113743086",[2,"AEZWTPeAMmbGLPIB","self:read","","coordinateSystem"]]
- */
-fn build_read_call(instance: u32, id: &str, key: &str) -> Message {
-    Message::text(format!(
-        "[{},\"3dconnexion:3dcontroller/{}\",[{},\"{}\",\"{}\",\"\",\"{}\"]]",
-        MessageType::Event as u32,
-        instance,
-        MessageType::Call as u32,
-        id,
-        "self:read",
-        key
-    ))
-}
-
-fn build_update_call(instance: u32, id: &str, key: &str, value: &str) -> Message {
-    Message::text(format!(
-        "[{},\"3dconnexion:3dcontroller/{}\",[{},\"{}\",\"{}\",\"\",\"{}\",{}]]",
-        MessageType::Event as u32,
-        instance,
-        MessageType::Call as u32,
-        id,
-        "self:update",
-        key,
-        value
-    ))
-}
-
-/*
-[8,"3dconnexion:3dcontroller/6884113743086",[2,"xqys5A4ZiD8E3lla","self:read","","selection.empty"]]
-[8,"3dconnexion:3dcontroller/3042851224",[2,"HxMA6bihFAoFhhHK","self::read","","selection.empty"]]
- */
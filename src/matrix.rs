@@ -1,81 +1,90 @@
-use crate::quat::Quat;
-use crate::spnav_posrot::Position;
+use crate::vector::{Vector, VectorOperationable};
 
+/// Column-major 4x4 matrix as the client sends `view.affine`: the camera's
+/// x, y and z axes in the first three columns, its position in the fourth
 pub type Matrix = [f32; 16];
+
+pub const IDENTITY: Matrix = [
+    1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+];
+
 pub trait MatrixOperationable {
-    fn obj(&mut self, pos: &Position);
-    fn view(&mut self, pos: &Position);
-    fn quat(&mut self, q: &Quat);
-    fn translation(&mut self, x: f32, y: f32, z: f32);
-    fn mul(&mut self, mb: &Matrix);
+    /// Column `i` of the rotation part
+    fn axis(&self, i: usize) -> Vector;
+    fn set_axis(&mut self, i: usize, v: &Vector);
+    fn position(&self) -> Vector;
+    /// Turns a direction in the frame's coordinates into world coordinates
+    fn to_world(&self, v: &Vector) -> Vector;
+    /// Moves the frame by a world space offset
+    fn translate(&mut self, v: &Vector);
+    /// Rotates the frame about the world point `center`, around the unit
+    /// world direction `axis`
+    fn rotate_about(&mut self, center: &Vector, axis: &Vector, angle: f32);
 }
 
 impl MatrixOperationable for Matrix {
-    fn obj(&mut self, pos: &Position) {
-        let mut tmp: Matrix = [0.0; 16];
-        self.quat(&pos.rot);
-        println!("BEF:  {:?}", self);
-        tmp.translation(pos.pos[0], pos.pos[1], pos.pos[2]);
-        self.mul(&tmp)
+    fn axis(&self, i: usize) -> Vector {
+        [self[i * 4], self[i * 4 + 1], self[i * 4 + 2]]
     }
 
-    fn view(&mut self, pos: &Position) {
-        let mut tmp: Matrix = [0.0; 16];
-        self.translation(pos.pos[0], pos.pos[1], pos.pos[2]);
-        println!("BEF:  {:?}", self);
-        tmp.quat(&pos.rot);
-        self.mul(&tmp)
+    fn set_axis(&mut self, i: usize, v: &Vector) {
+        self[i * 4..i * 4 + 3].copy_from_slice(v);
     }
 
-    fn quat(&mut self, q: &Quat) {
-        let xsq2 = 2.0 * q[0] * q[0];
-        let ysq2 = 2.0 * q[1] * q[1];
-        let zsq2 = 2.0 * q[2] * q[2];
-        let sx = 1.0 - ysq2 - zsq2;
-        let sy = 1.0 - xsq2 - zsq2;
-        let sz = 1.0 - xsq2 - ysq2;
-
-        self[3] = 0.0;
-        self[7] = 0.0;
-        self[11] = 0.0;
-        self[12] = 0.0;
-        self[13] = 0.0;
-        self[14] = 0.0;
-        self[15] = 1.0;
+    fn position(&self) -> Vector {
+        self.axis(3)
+    }
 
-        self[0] = sx;
-        self[1] = 2.0 * q[0] * q[1] + 2.0 * q[3] * q[2];
-        self[2] = 2.0 * q[2] * q[0] - 2.0 * q[3] * q[1];
-        self[4] = 2.0 * q[0] * q[1] - 2.0 * q[3] * q[2];
-        self[5] = sy;
-        self[6] = 2.0 * q[1] * q[2] + 2.0 * q[3] * q[0];
-        self[8] = 2.0 * q[2] * q[0] + 2.0 * q[3] * q[1];
-        self[9] = 2.0 * q[1] * q[2] - 2.0 * q[3] * q[0];
-        self[10] = sz;
+    fn to_world(&self, v: &Vector) -> Vector {
+        self.axis(0)
+            .scale(v[0])
+            .add(&self.axis(1).scale(v[1]))
+            .add(&self.axis(2).scale(v[2]))
     }
 
-    fn translation(&mut self, x: f32, y: f32, z: f32) {
-        // [1,0,0,0,0,0,-1,0,0,1,0,0,0,0,0,1]
-        *self = [
-            1.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0,
-        ];
-        self[12] = x;
-        self[13] = y;
-        self[14] = z;
+    fn translate(&mut self, v: &Vector) {
+        let position = self.position().add(v);
+        self.set_axis(3, &position);
     }
 
-    fn mul(&mut self, mb: &Matrix) {
-        let mut tmp: Matrix = [0.0; 16];
-        let mut tmp_index = 0;
-        for row in 0..4 {
-            for j in 0..4 {
-                tmp[tmp_index] = self[row * 4] * mb[j]
-                    + self[row * 4 + 1] * mb[4 + j]
-                    + self[row * 4 + 2] * mb[8 + j]
-                    + self[row * 4 + 3] * mb[12 + j];
-                tmp_index += 1;
-            }
+    fn rotate_about(&mut self, center: &Vector, axis: &Vector, angle: f32) {
+        for i in 0..3 {
+            let rotated = self.axis(i).rotated(axis, angle);
+            self.set_axis(i, &rotated);
         }
-        *self = tmp;
+        let position = self.position().sub(center).rotated(axis, angle).add(center);
+        self.set_axis(3, &position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn assert_close(a: &Matrix, b: &Matrix) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn rotate_about_point() {
+        // Camera at z = 5 looking down -z at the origin
+        let mut camera = IDENTITY;
+        camera.translate(&[0.0, 0.0, 5.0]);
+
+        // A quarter turn around y moves it to x = 5, looking down -x
+        camera.rotate_about(&[0.0, 0.0, 0.0], &[0.0, 1.0, 0.0], FRAC_PI_2);
+        assert_close(
+            &camera,
+            &[
+                0.0, 0.0, -1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 5.0, 0.0, 0.0, 1.0,
+            ],
+        );
+        let forward = camera.to_world(&[0.0, 0.0, -1.0]);
+        assert!(forward.sub(&[-1.0, 0.0, 0.0]).length() < 1e-5);
     }
 }
@@ -0,0 +1,181 @@
+use tokio::time::Instant;
+
+use crate::spnav::{spnav_event_motion, SPNAV_EVENT_MOTION};
+
+/// Integrates the device deflection over time so each client frame can
+/// consume everything that happened since the previous one.
+///
+/// spacenavd only reports changes, so a held deflection keeps counting until
+/// the next event replaces it. Time at rest is not counted: frames are not
+/// taken then, and a motion after a pause must not be averaged over it.
+pub struct MotionAccumulator {
+    current: [f32; 6],
+    since: Instant,
+    /// Deflection multiplied by milliseconds
+    sum: [f32; 6],
+    elapsed_ms: f32,
+}
+impl MotionAccumulator {
+    pub fn new(now: Instant) -> MotionAccumulator {
+        MotionAccumulator {
+            current: [0.0; 6],
+            since: now,
+            sum: [0.0; 6],
+            elapsed_ms: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, motion: &spnav_event_motion, now: Instant) {
+        self.integrate(now);
+        self.current = [
+            motion.x as f32,
+            motion.y as f32,
+            motion.z as f32,
+            motion.rx as f32,
+            motion.ry as f32,
+            motion.rz as f32,
+        ];
+    }
+
+    /// Drops the motion accumulated so far, keeping the current deflection
+    pub fn discard(&mut self, now: Instant) {
+        self.sum = [0.0; 6];
+        self.elapsed_ms = 0.0;
+        self.since = now;
+    }
+
+    /// True while the device rests in its centre position
+    pub fn is_idle(&self) -> bool {
+        self.current.iter().all(|v| *v == 0.0)
+    }
+
+    /// True while the cap is tilted or twisted, not only pushed
+    pub fn is_rotating(&self) -> bool {
+        self.current[3..].iter().any(|v| *v != 0.0)
+    }
+
+    /// Returns the average deflection since the last call with `period` set to
+    /// the milliseconds the device was deflected, or None if it did not move.
+    pub fn take(&mut self, now: Instant) -> Option<spnav_event_motion> {
+        self.integrate(now);
+
+        let sum = self.sum;
+        let elapsed_ms = self.elapsed_ms;
+        self.sum = [0.0; 6];
+        self.elapsed_ms = 0.0;
+
+        if elapsed_ms <= 0.0 || sum.iter().all(|v| *v == 0.0) {
+            return None;
+        }
+
+        let avg = |i: usize| (sum[i] / elapsed_ms).round() as i32;
+        Some(spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            x: avg(0),
+            y: avg(1),
+            z: avg(2),
+            rx: avg(3),
+            ry: avg(4),
+            rz: avg(5),
+            period: elapsed_ms.round() as u32,
+        })
+    }
+
+    fn integrate(&mut self, now: Instant) {
+        let dt = now.saturating_duration_since(self.since).as_secs_f32() * 1000.0;
+        self.since = now;
+        if self.is_idle() {
+            return;
+        }
+        for (sum, current) in self.sum.iter_mut().zip(self.current) {
+            *sum += current * dt;
+        }
+        self.elapsed_ms += dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn motion(x: i32, rz: i32) -> spnav_event_motion {
+        spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            x,
+            rz,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn averages_over_frame() {
+        let start = Instant::now();
+        let mut acc = MotionAccumulator::new(start);
+        assert!(acc.is_idle());
+
+        acc.push(&motion(100, -20), start);
+        acc.push(&motion(300, -20), start + Duration::from_millis(10));
+        assert!(!acc.is_idle());
+        assert!(acc.is_rotating());
+
+        let frame = acc.take(start + Duration::from_millis(20)).unwrap();
+        assert_eq!(frame.x, 200);
+        assert_eq!(frame.rz, -20);
+        assert_eq!(frame.period, 20);
+    }
+
+    #[test]
+    fn held_deflection_keeps_moving() {
+        let start = Instant::now();
+        let mut acc = MotionAccumulator::new(start);
+        acc.push(&motion(50, 0), start);
+        assert!(!acc.is_rotating());
+
+        acc.take(start + Duration::from_millis(16)).unwrap();
+        let frame = acc.take(start + Duration::from_millis(32)).unwrap();
+        assert_eq!(frame.x, 50);
+        assert_eq!(frame.period, 16);
+    }
+
+    #[test]
+    fn idle_after_release() {
+        let start = Instant::now();
+        let mut acc = MotionAccumulator::new(start);
+        acc.push(&motion(50, 0), start);
+        acc.push(&motion(0, 0), start + Duration::from_millis(8));
+
+        assert!(acc.is_idle());
+        assert!(acc.take(start + Duration::from_millis(16)).is_some());
+        assert!(acc.take(start + Duration::from_millis(32)).is_none());
+    }
+
+    #[test]
+    fn discard_keeps_deflection() {
+        let start = Instant::now();
+        let mut acc = MotionAccumulator::new(start);
+        acc.push(&motion(50, 0), start);
+        acc.discard(start + Duration::from_millis(100));
+        assert!(!acc.is_idle());
+
+        let frame = acc.take(start + Duration::from_millis(116)).unwrap();
+        assert_eq!(frame.x, 50);
+        assert_eq!(frame.period, 16);
+    }
+
+    #[test]
+    fn rest_is_not_averaged_in() {
+        let start = Instant::now();
+        let mut acc = MotionAccumulator::new(start);
+        acc.push(&motion(50, 0), start);
+        acc.push(&motion(0, 0), start + Duration::from_millis(8));
+        acc.take(start + Duration::from_millis(16)).unwrap();
+
+        // Picked up again a minute later, no frames in between
+        let later = start + Duration::from_secs(60);
+        acc.push(&motion(200, 0), later);
+        let frame = acc.take(later + Duration::from_millis(16)).unwrap();
+        assert_eq!(frame.x, 200);
+        assert_eq!(frame.period, 16);
+    }
+}
@@ -0,0 +1,152 @@
+use std::{io, path::Path};
+
+use serde_json::{json, Value};
+use tokio::{
+    sync::broadcast,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    json_lines,
+    spnav::{
+        spnav_event, spnav_event_button, spnav_event_motion, SPNAV_EVENT_BUTTON, SPNAV_EVENT_MOTION,
+    },
+};
+
+/*
+Device events as JSON lines, with milliseconds since the recording started:
+{"time":0,"motion":[0,0,-35,2,0,0],"period":16}
+{"time":412,"button":0,"press":true}
+ */
+pub fn to_line(time_ms: u64, event: &spnav_event) -> String {
+    let value = match event {
+        spnav_event::Motion(m) => json!({
+            "time": time_ms,
+            "motion": [m.x, m.y, m.z, m.rx, m.ry, m.rz],
+            "period": m.period,
+        }),
+        spnav_event::Button(b) => json!({
+            "time": time_ms,
+            "button": b.bnum,
+            "press": b.press,
+        }),
+    };
+    value.to_string()
+}
+
+pub fn parse_line(line: &str) -> Result<(u64, spnav_event), String> {
+    let value: Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let int = |key: &str| {
+        value[key]
+            .as_i64()
+            .and_then(|v| i32::try_from(v).ok())
+            .ok_or_else(|| format!("{key} must be an integer"))
+    };
+    let time = value["time"]
+        .as_u64()
+        .ok_or("time must be a positive integer")?;
+
+    let event = if let Some(axes) = value["motion"].as_array() {
+        let axes = axes
+            .iter()
+            .map(|v| v.as_i64().and_then(|v| i32::try_from(v).ok()))
+            .collect::<Option<Vec<_>>>()
+            .filter(|axes| axes.len() == 6)
+            .ok_or("motion must be 6 integers")?;
+        spnav_event::Motion(spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            x: axes[0],
+            y: axes[1],
+            z: axes[2],
+            rx: axes[3],
+            ry: axes[4],
+            rz: axes[5],
+            period: int("period")? as u32,
+        })
+    } else {
+        spnav_event::Button(spnav_event_button {
+            event_type: SPNAV_EVENT_BUTTON,
+            bnum: int("button")?,
+            press: value["press"].as_bool().ok_or("press must be a boolean")?,
+        })
+    };
+    Ok((time, event))
+}
+
+/// Reads a whole recording, reporting the first bad line by number. A
+/// recording without events is an error, there would be nothing to replay.
+pub fn load(path: &Path) -> io::Result<Vec<(u64, spnav_event)>> {
+    let records = json_lines::load(path, parse_line)?;
+    if records.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no events recorded", path.display()),
+        ));
+    }
+    Ok(records)
+}
+
+/// Sends recorded events with their original timing, in place of the device
+/// task. With `repeat` the recording starts over once it is done.
+pub async fn replay(
+    records: Vec<(u64, spnav_event)>,
+    events: broadcast::Sender<spnav_event>,
+    repeat: bool,
+) {
+    loop {
+        let start = Instant::now();
+        for (time, event) in &records {
+            tokio::time::sleep_until(start + Duration::from_millis(*time)).await;
+            let _ = events.send(*event);
+        }
+        if !repeat {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let motion = spnav_event::Motion(spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            x: 1,
+            y: -2,
+            z: 3,
+            rx: -4,
+            ry: 5,
+            rz: -6,
+            period: 16,
+        });
+        let button = spnav_event::Button(spnav_event_button {
+            event_type: SPNAV_EVENT_BUTTON,
+            press: false,
+            bnum: 3,
+        });
+
+        let line = to_line(20, &motion);
+        assert_eq!(line, r#"{"motion":[1,-2,3,-4,5,-6],"period":16,"time":20}"#);
+        assert_eq!(parse_line(&line), Ok((20, motion)));
+        assert_eq!(parse_line(&to_line(412, &button)), Ok((412, button)));
+
+        assert!(parse_line(r#"{"time":0,"motion":[1,2,3]}"#).is_err());
+        assert!(parse_line(r#"{"time":-1,"button":0,"press":true}"#).is_err());
+    }
+
+    #[test]
+    fn load_rejects_empty() {
+        let path =
+            std::env::temp_dir().join(format!("spacenav-web-motion-{}.jsonl", std::process::id()));
+        std::fs::write(&path, "\n{\"time\":412,\"button\":0,\"press\":true}\n").unwrap();
+        assert_eq!(load(&path).unwrap().len(), 1);
+
+        std::fs::write(&path, "\n  \n").unwrap();
+        let err = load(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
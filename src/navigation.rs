@@ -0,0 +1,653 @@
+use serde::Deserialize;
+
+use crate::{
+    matrix::{Matrix, MatrixOperationable},
+    properties::{Extents, Frustum, Properties},
+    spnav::spnav_event_motion,
+    vector::{Vector, VectorOperationable},
+};
+
+/// Deflection spacenavd reports with the cap pushed all the way
+const FULL_DEFLECTION: f32 = 350.0;
+/// Pan lengths per second at full deflection, see `pan_length`
+const TRANSLATION_SPEED: f32 = 1.0;
+/// Degrees per second at full deflection
+const ROTATION_SPEED: f32 = 90.0;
+/// Shares of the distance per second at full deflection, see `distance`
+const ZOOM_SPEED: f32 = 1.0;
+/// Closest the distance gets, as a share of the model's diagonal or in
+/// units without a model
+const MIN_DISTANCE: f32 = 0.001;
+/// Share of the visible width the hit-test ray covers
+const HIT_APERTURE: f32 = 0.01;
+
+/// What the cap is taken to move
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// The model, rotating about the pivot
+    #[default]
+    Object,
+    /// The camera, rotating about its own position
+    Camera,
+    /// The camera, orbiting `view.target` and panning it along
+    #[serde(alias = "orbit")]
+    Target,
+    /// The camera like `Camera`, but turning only around the up axis and
+    /// pitching, so the horizon stays level. Pushing the cap flies forward
+    /// instead of zooming.
+    Fly,
+}
+impl Mode {
+    /// Order in which a mode button cycles through the modes
+    pub fn next(self) -> Mode {
+        match self {
+            Mode::Object => Mode::Camera,
+            Mode::Camera => Mode::Target,
+            Mode::Target => Mode::Fly,
+            Mode::Fly => Mode::Object,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+}
+impl Projection {
+    /// Clients which never answered `view.perspective` are treated as perspective
+    pub fn of(props: &Properties) -> Projection {
+        match props.view_perspective {
+            Some(false) => Projection::Orthographic,
+            _ => Projection::Perspective,
+        }
+    }
+}
+
+/// Centre of rotation: a pivot the user placed in the application, else
+/// what the last hit test found, else the selection if there is one,
+/// otherwise the whole model, falling back to the camera target when no
+/// extents are known.
+pub fn pivot(props: &Properties) -> Option<Vector> {
+    if props.pivot_user == Some(true) {
+        if let Some(position) = props.pivot_position {
+            return Some(position);
+        }
+    }
+    if let Some(lookat) = props.hit_lookat {
+        return Some(lookat);
+    }
+    let selection = match props.selection_empty {
+        Some(false) => props.selection_extents.as_ref(),
+        _ => None,
+    };
+    selection
+        .or(props.model_extents.as_ref())
+        .map(Extents::center)
+        .or(props.view_target)
+}
+
+/// Pick through the centre of the screen, sent as `hit.lookfrom`,
+/// `hit.direction` and `hit.aperture`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitRay {
+    pub lookfrom: Vector,
+    pub direction: Vector,
+    /// Diameter of the ray in world units
+    pub aperture: f32,
+}
+
+/// Horizontal field of view in radians: `view.fov`, else the angle the
+/// frustum spans at its near plane
+fn fov(props: &Properties) -> Option<f32> {
+    props.view_fov.or_else(|| {
+        let f = props.view_frustum.filter(|f| f.near > 0.0)?;
+        Some((f.right / f.near).atan() - (f.left / f.near).atan())
+    })
+}
+
+/// The ray along the camera's view axis. Its width is a small share of what
+/// is visible: the view extents when orthographic, the field of view at the
+/// distance of the model when perspective. Without either it is thin.
+pub fn hit_ray(props: &Properties) -> Option<HitRay> {
+    let camera = props.view_affine?;
+    let lookfrom = camera.position();
+    let direction = camera.axis(2).scale(-1.0).normalized()?;
+    let width = match Projection::of(props) {
+        Projection::Orthographic => props.view_extents.map(|e| e.max[0] - e.min[0]),
+        Projection::Perspective => {
+            let model = props.model_extents.as_ref().map(Extents::center);
+            let distance = model
+                .or(props.view_target)
+                .map(|point| point.sub(&lookfrom).length());
+            distance
+                .zip(fov(props))
+                .map(|(distance, fov)| 2.0 * distance * (fov * 0.5).tan())
+        }
+    };
+    Some(HitRay {
+        lookfrom,
+        direction,
+        aperture: width.unwrap_or(0.0).abs() * HIT_APERTURE,
+    })
+}
+
+/// Point the camera rotates about in `mode`, None for its own position
+pub fn center(mode: Mode, props: &Properties) -> Option<Vector> {
+    match mode {
+        Mode::Object => pivot(props),
+        Mode::Target => props.view_target,
+        Mode::Camera | Mode::Fly => None,
+    }
+}
+
+/// Up direction of the client's world. `coordinateSystem` maps the client's
+/// coordinates to ones with y up, so up is its second row.
+pub fn world_up(props: &Properties) -> Vector {
+    props
+        .coordinate_system
+        .and_then(|cs| [cs[1], cs[5], cs[9]].normalized())
+        .unwrap_or([0.0, 1.0, 0.0])
+}
+
+/// The camera state navigation changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub affine: Matrix,
+    /// What the camera rotates about, see `center`
+    pub center: Option<Vector>,
+    /// Visible volume of an orthographic view in camera coordinates
+    pub extents: Option<Extents>,
+    /// Viewing volume, its near plane bounds the dolly of a perspective view
+    pub frustum: Option<Frustum>,
+}
+
+/// What navigation needs to know about the client's scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scene {
+    pub projection: Projection,
+    pub up: Vector,
+    pub model: Option<Extents>,
+}
+impl Scene {
+    pub fn of(props: &Properties) -> Scene {
+        Scene {
+            projection: Projection::of(props),
+            up: world_up(props),
+            model: props.model_extents,
+        }
+    }
+}
+
+/// Distance the zoom speed is relative to: to the centre of rotation, else
+/// to the model. Never zero, the camera would stop short of it.
+fn distance(view: &View, scene: &Scene) -> f32 {
+    let eye = view.affine.position();
+    let model = scene.model.as_ref();
+    let distance = view
+        .center
+        .or(model.map(Extents::center))
+        .map_or(1.0, |point| point.sub(&eye).length());
+    let min = model.map_or(MIN_DISTANCE, |model| {
+        model.max.sub(&model.min).length() * MIN_DISTANCE
+    });
+    distance.max(min)
+}
+
+/// Length the pan speed is relative to, so a part and an assembly both
+/// cross the screen in about the same time: the visible width of an
+/// orthographic view, the distance to what is looked at otherwise.
+fn pan_length(view: &View, scene: &Scene) -> f32 {
+    match (scene.projection, view.extents) {
+        (Projection::Orthographic, Some(extents)) => (extents.max[0] - extents.min[0]).abs(),
+        _ => distance(view, scene),
+    }
+}
+
+/// Dollies a perspective camera so the zoom distance is scaled by
+/// `amount.exp()`, positive backwards, stopping where the centre would fall
+/// in front of the near plane. Orthographic views keep the camera and scale
+/// their extents and frustum instead, moving closer has no effect on screen
+/// there.
+fn zoom(view: &mut View, scene: &Scene, amount: f32) {
+    if amount == 0.0 {
+        return;
+    }
+    let factor = amount.exp();
+    match scene.projection {
+        Projection::Perspective => {
+            // Exponential, so no amount moves the camera past the centre
+            let distance = distance(view, scene);
+            let near = view.frustum.map_or(0.0, |frustum| frustum.near);
+            let zoomed = (distance * factor).max(near.min(distance));
+            let offset = view.affine.to_world(&[0.0, 0.0, zoomed - distance]);
+            view.affine.translate(&offset);
+        }
+        Projection::Orthographic => {
+            if let Some(extents) = &mut view.extents {
+                for v in [&mut extents.min, &mut extents.max] {
+                    v[0] *= factor;
+                    v[1] *= factor;
+                }
+            }
+            if let Some(frustum) = &mut view.frustum {
+                for v in [
+                    &mut frustum.left,
+                    &mut frustum.right,
+                    &mut frustum.bottom,
+                    &mut frustum.top,
+                ] {
+                    *v *= factor;
+                }
+            }
+        }
+    }
+}
+
+/// Moves the camera by one frame of averaged device motion, held for the
+/// frame's `period`. Device axes are taken in camera space: x right, y up,
+/// z towards the user. In target mode panning carries the centre along, in
+/// fly mode z moves the eye along its view direction in both projections.
+pub fn step(mode: Mode, view: &mut View, scene: &Scene, motion: &spnav_event_motion) {
+    // Moving the model one way is moving the camera the other way
+    let sign = match mode {
+        Mode::Object | Mode::Target => -1.0,
+        Mode::Camera | Mode::Fly => 1.0,
+    };
+    // Seconds at full deflection the frame amounts to
+    let held = motion.period as f32 / 1000.0 / FULL_DEFLECTION;
+    let translation = sign * held * TRANSLATION_SPEED * pan_length(view, scene);
+    let forward = if mode == Mode::Fly {
+        motion.z as f32
+    } else {
+        0.0
+    };
+    let camera = &mut view.affine;
+    let pan = camera.to_world(&[motion.x as f32, motion.y as f32, forward].scale(translation));
+    let rotation =
+        [motion.rx, motion.ry, motion.rz].map(|v| v as f32 * held * ROTATION_SPEED.to_radians());
+    let eye = camera.position();
+
+    match mode {
+        Mode::Object | Mode::Target => {
+            if let Some(axis) = camera.to_world(&rotation).normalized() {
+                let about = view.center.unwrap_or(eye);
+                camera.rotate_about(&about, &axis, -rotation.length());
+            }
+        }
+        Mode::Camera => {
+            if let Some(axis) = camera.to_world(&rotation).normalized() {
+                camera.rotate_about(&eye, &axis, rotation.length());
+            }
+        }
+        Mode::Fly => {
+            let pitch_axis = camera.axis(0);
+            camera.rotate_about(&eye, &scene.up, rotation[1]);
+            camera.rotate_about(&eye, &pitch_axis, rotation[0]);
+            level(camera, &scene.up);
+        }
+    }
+    camera.translate(&pan);
+    if mode == Mode::Target {
+        // Only panning moves the target, pushing the cap zooms towards it
+        view.center = view.center.map(|center| center.add(&pan));
+    }
+    if mode != Mode::Fly {
+        zoom(view, scene, motion.z as f32 * sign * held * ZOOM_SPEED);
+    }
+}
+
+/// Removes any roll, keeping the view direction. Looking straight up or
+/// down leaves the camera as it is.
+fn level(camera: &mut Matrix, up: &Vector) {
+    let forward = camera.axis(2).scale(-1.0);
+    let right = match forward.cross(up).normalized() {
+        Some(right) => right,
+        None => return,
+    };
+    let Some(forward) = forward.normalized() else {
+        return;
+    };
+    camera.set_axis(0, &right);
+    camera.set_axis(1, &right.cross(&forward));
+    camera.set_axis(2, &forward.scale(-1.0));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    use super::*;
+    use crate::matrix::IDENTITY;
+    use serde_json::json;
+
+    #[test]
+    fn pivot_prefers_selection() {
+        let mut props = Properties::default();
+        assert_eq!(pivot(&props), None);
+
+        props.set("view.target", &json!([9.0, 9.0, 9.0])).unwrap();
+        assert_eq!(pivot(&props), Some([9.0, 9.0, 9.0]));
+
+        props
+            .set("model.extents", &json!([0.0, 0.0, 0.0, 2.0, 2.0, 2.0]))
+            .unwrap();
+        assert_eq!(pivot(&props), Some([1.0, 1.0, 1.0]));
+
+        props
+            .set("selection.extents", &json!([4.0, 4.0, 4.0, 6.0, 6.0, 6.0]))
+            .unwrap();
+        props.set("selection.empty", &json!(true)).unwrap();
+        assert_eq!(pivot(&props), Some([1.0, 1.0, 1.0]));
+
+        props.set("selection.empty", &json!(false)).unwrap();
+        assert_eq!(pivot(&props), Some([5.0, 5.0, 5.0]));
+
+        // Only a pivot the user placed wins over the selection
+        props
+            .set("pivot.position", &json!([7.0, 0.0, 0.0]))
+            .unwrap();
+        assert_eq!(pivot(&props), Some([5.0, 5.0, 5.0]));
+        props.set("hit.lookat", &json!([8.0, 0.0, 0.0])).unwrap();
+        assert_eq!(pivot(&props), Some([8.0, 0.0, 0.0]));
+        props.set("pivot.user", &json!(true)).unwrap();
+        assert_eq!(pivot(&props), Some([7.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn projection() {
+        let mut props = Properties::default();
+        assert_eq!(Projection::of(&props), Projection::Perspective);
+        props.set("view.perspective", &json!(false)).unwrap();
+        assert_eq!(Projection::of(&props), Projection::Orthographic);
+    }
+
+    fn motion(values: [i32; 6]) -> spnav_event_motion {
+        spnav_event_motion {
+            x: values[0],
+            y: values[1],
+            z: values[2],
+            rx: values[3],
+            ry: values[4],
+            rz: values[5],
+            period: 100,
+            ..Default::default()
+        }
+    }
+
+    /// Camera at z = 5 looking at the origin
+    fn camera() -> Matrix {
+        let mut camera = IDENTITY;
+        camera.translate(&[0.0, 0.0, 5.0]);
+        camera
+    }
+
+    fn view(center: Option<Vector>) -> View {
+        View {
+            affine: camera(),
+            center,
+            extents: None,
+            frustum: None,
+        }
+    }
+
+    fn close(a: &Vector, b: &Vector) -> bool {
+        a.sub(b).length() < 1e-4
+    }
+
+    const SCENE: Scene = Scene {
+        projection: Projection::Perspective,
+        up: [0.0, 1.0, 0.0],
+        model: None,
+    };
+
+    #[test]
+    fn object_and_camera_move_opposite() {
+        // A tenth of a second at full deflection
+        let right = motion([350, 0, 0, 0, 0, 0]);
+        let mut object = view(None);
+        step(Mode::Object, &mut object, &SCENE, &right);
+        assert!(close(&object.affine.position(), &[-0.1, 0.0, 5.0]));
+
+        let mut cam = view(None);
+        step(Mode::Camera, &mut cam, &SCENE, &right);
+        assert!(close(&cam.affine.position(), &[0.1, 0.0, 5.0]));
+    }
+
+    #[test]
+    fn pan_scales_with_model_and_view() {
+        let right = motion([350, 0, 0, 0, 0, 0]);
+        // Looking at a model 5 units away pans half a unit
+        let model = Scene {
+            model: Some(Extents {
+                min: [-1.0, -1.0, -1.0],
+                max: [1.0, 1.0, 1.0],
+            }),
+            ..SCENE
+        };
+        let mut view = view(None);
+        step(Mode::Camera, &mut view, &model, &right);
+        assert!(close(&view.affine.position(), &[0.5, 0.0, 5.0]));
+
+        // An orthographic view pans by its visible width
+        let ortho = Scene {
+            projection: Projection::Orthographic,
+            ..model
+        };
+        let mut view = View {
+            extents: Some(Extents {
+                min: [-2.0, -1.0, 0.0],
+                max: [2.0, 1.0, 10.0],
+            }),
+            ..view
+        };
+        step(Mode::Camera, &mut view, &ortho, &right);
+        assert!(close(&view.affine.position(), &[0.9, 0.0, 5.0]));
+    }
+
+    #[test]
+    fn rotation_in_degrees_per_second() {
+        // Half a second at full deflection turns 45 degrees
+        let turn = spnav_event_motion {
+            period: 500,
+            ..motion([0, 0, 0, 0, 350, 0])
+        };
+        let mut view = view(None);
+        step(Mode::Camera, &mut view, &SCENE, &turn);
+        let forward = view.affine.to_world(&[0.0, 0.0, -1.0]);
+        let half = FRAC_PI_4.sin();
+        assert!(close(&forward, &[-half, 0.0, -half]));
+        assert!(close(&view.affine.position(), &[0.0, 0.0, 5.0]));
+    }
+
+    #[test]
+    fn object_rotates_about_pivot() {
+        // Twisting the cap counterclockwise turns the model that way, so the
+        // camera goes round the other way
+        let turn = motion([0, 0, 0, 0, 500, 0]);
+        let mut view = view(Some([0.0, 0.0, 0.0]));
+        step(Mode::Object, &mut view, &SCENE, &turn);
+
+        let eye = view.affine.position();
+        assert!((eye.length() - 5.0).abs() < 1e-4);
+        assert!(eye[0] < 0.0);
+        // Still looking at the pivot
+        let forward = view.affine.to_world(&[0.0, 0.0, -1.0]);
+        assert!(close(&forward, &eye.scale(-1.0 / 5.0)));
+        assert_eq!(view.center, Some([0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn target_pans_along() {
+        let mut view = view(Some([0.0, 0.0, 0.0]));
+        step(
+            Mode::Target,
+            &mut view,
+            &SCENE,
+            &motion([0, 350, 0, 0, 0, 0]),
+        );
+        // Panning relative to the distance of the target
+        assert!(close(&view.affine.position(), &[0.0, -0.5, 5.0]));
+        assert!(close(&view.center.unwrap(), &[0.0, -0.5, 0.0]));
+    }
+
+    #[test]
+    fn zoom_relative_to_distance() {
+        // Pulling the cap brings the model closer, by a share of its distance
+        let pull = motion([0, 0, 350, 0, 0, 0]);
+        let factor = (-0.1f32).exp();
+        let mut near = view(Some([0.0, 0.0, 4.0]));
+        step(Mode::Object, &mut near, &SCENE, &pull);
+        assert!(close(&near.affine.position(), &[0.0, 0.0, 4.0 + factor]));
+        let mut far = view(Some([0.0, 0.0, -5.0]));
+        step(Mode::Object, &mut far, &SCENE, &pull);
+        assert!(close(
+            &far.affine.position(),
+            &[0.0, 0.0, -5.0 + 10.0 * factor]
+        ));
+        assert_eq!(far.center, Some([0.0, 0.0, -5.0]));
+
+        // However long the cap is held, the camera stops short of the centre
+        let held = spnav_event_motion {
+            period: 5000,
+            ..pull
+        };
+        step(Mode::Object, &mut near, &SCENE, &held);
+        let eye = near.affine.position();
+        assert!(eye[2] > 4.0 && eye[2] < 4.0 + factor);
+
+        // Nor does it bring the centre in front of the near plane
+        let mut clipped = View {
+            frustum: Some(Frustum {
+                left: -0.1,
+                right: 0.1,
+                bottom: -0.1,
+                top: 0.1,
+                near: 0.5,
+                far: 100.0,
+            }),
+            ..view(Some([0.0, 0.0, 4.0]))
+        };
+        step(Mode::Object, &mut clipped, &SCENE, &held);
+        assert!(close(&clipped.affine.position(), &[0.0, 0.0, 4.5]));
+
+        // Orthographic views shrink what they show instead
+        let ortho = Scene {
+            projection: Projection::Orthographic,
+            ..SCENE
+        };
+        let mut view = View {
+            extents: Some(Extents {
+                min: [-2.0, -1.0, 0.0],
+                max: [2.0, 1.0, 10.0],
+            }),
+            frustum: Some(Frustum {
+                left: -2.0,
+                right: 2.0,
+                bottom: -1.0,
+                top: 1.0,
+                near: 0.0,
+                far: 10.0,
+            }),
+            ..view(None)
+        };
+        step(Mode::Object, &mut view, &ortho, &pull);
+        assert_eq!(view.affine, camera());
+        let extents = view.extents.unwrap();
+        assert!(close(&extents.min, &[-2.0 * factor, -factor, 0.0]));
+        assert!(close(&extents.max, &[2.0 * factor, factor, 10.0]));
+        let frustum = view.frustum.unwrap();
+        assert!(close(
+            &[frustum.left, frustum.top, frustum.far],
+            &[-2.0 * factor, factor, 10.0]
+        ));
+    }
+
+    #[test]
+    fn fly_keeps_horizon() {
+        let mut view = view(None);
+        for _ in 0..20 {
+            step(
+                Mode::Fly,
+                &mut view,
+                &SCENE,
+                &motion([0, 0, -100, 20, 300, 400]),
+            );
+        }
+        // No roll: the right axis stays horizontal
+        let camera = view.affine;
+        assert!(camera.axis(0)[1].abs() < 1e-4);
+        assert!(camera.axis(1)[1] > 0.0);
+        assert!(camera.position() != [0.0, 0.0, 5.0]);
+    }
+
+    #[test]
+    fn fly_moves_eye_forward() {
+        // Pushing the cap flies towards what is looked at, by the pan speed
+        let push = motion([0, 0, -350, 0, 0, 0]);
+        let mut persp = view(Some([0.0, 0.0, 0.0]));
+        step(Mode::Fly, &mut persp, &SCENE, &push);
+        assert!(close(&persp.affine.position(), &[0.0, 0.0, 4.5]));
+
+        // Orthographic views fly the same way, what they show stays
+        let ortho = Scene {
+            projection: Projection::Orthographic,
+            ..SCENE
+        };
+        let extents = Extents {
+            min: [-2.0, -1.0, 0.0],
+            max: [2.0, 1.0, 10.0],
+        };
+        let mut view = View {
+            extents: Some(extents),
+            ..view(None)
+        };
+        step(Mode::Fly, &mut view, &ortho, &push);
+        assert!(close(&view.affine.position(), &[0.0, 0.0, 4.6]));
+        assert_eq!(view.extents, Some(extents));
+    }
+
+    #[test]
+    fn hit_ray_along_view_axis() {
+        let mut props = Properties::default();
+        assert_eq!(hit_ray(&props), None);
+
+        props.set("view.affine", &json!(camera())).unwrap();
+        let ray = hit_ray(&props).unwrap();
+        assert_eq!(ray.lookfrom, [0.0, 0.0, 5.0]);
+        assert_eq!(ray.direction, [0.0, 0.0, -1.0]);
+        assert_eq!(ray.aperture, 0.0);
+
+        // 90 degrees seen from 5 units away is 10 units wide
+        props.set("view.fov", &json!(FRAC_PI_2)).unwrap();
+        props.set("view.target", &json!([0.0, 0.0, 0.0])).unwrap();
+        assert!((hit_ray(&props).unwrap().aperture - 0.1).abs() < 1e-5);
+        let mut frustum = props.clone();
+        frustum.view_fov = None;
+        frustum
+            .set("view.frustum", &json!([-0.5, 0.5, -0.25, 0.25, 0.5, 100.0]))
+            .unwrap();
+        assert!((hit_ray(&frustum).unwrap().aperture - 0.1).abs() < 1e-5);
+
+        props.set("view.perspective", &json!(false)).unwrap();
+        props
+            .set("view.extents", &json!([-2.0, -1.0, 0.0, 2.0, 1.0, 10.0]))
+            .unwrap();
+        assert!((hit_ray(&props).unwrap().aperture - 0.04).abs() < 1e-5);
+    }
+
+    #[test]
+    fn world_up_from_coordinate_system() {
+        let mut props = Properties::default();
+        assert_eq!(world_up(&props), SCENE.up);
+        // z up application: its z axis is mapped to y
+        props
+            .set(
+                "coordinateSystem",
+                &json!([1, 0, 0, 0, 0, 0, -1, 0, 0, 1, 0, 0, 0, 0, 0, 1]),
+            )
+            .unwrap();
+        assert!(close(&world_up(&props), &[0.0, 0.0, 1.0]));
+    }
+}
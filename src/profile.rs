@@ -0,0 +1,365 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use serde::Deserialize;
+use tokio::{sync::watch, time::Duration};
+
+use crate::{
+    config,
+    keys::ButtonMap,
+    navigation::Mode,
+    spnav::{spnav_event_motion, SPNAV_EVENT_MOTION},
+};
+
+/// How often the profile directory is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Which clients a profile applies to. Fields left out match anything.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Match {
+    /// `name` from the 3dcontroller create arguments, ignoring case
+    pub name: Option<String>,
+    /// `commands.activeSet` of the client
+    pub active_set: Option<String>,
+}
+impl Match {
+    /// Number of matching fields, None if any field does not match
+    fn specificity(&self, name: Option<&str>, active_set: Option<&str>) -> Option<usize> {
+        let mut count = 0;
+        if let Some(expected) = &self.name {
+            if !name.is_some_and(|name| name.eq_ignore_ascii_case(expected)) {
+                return None;
+            }
+            count += 1;
+        }
+        if let Some(expected) = &self.active_set {
+            if active_set != Some(expected.as_str()) {
+                return None;
+            }
+            count += 1;
+        }
+        Some(count)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Axis {
+    pub sensitivity: f32,
+    pub invert: bool,
+    /// Deflections up to this are treated as the cap resting
+    pub dead_zone: i32,
+}
+impl Default for Axis {
+    fn default() -> Axis {
+        Axis {
+            sensitivity: 1.0,
+            invert: false,
+            dead_zone: 0,
+        }
+    }
+}
+impl Axis {
+    fn apply(&self, value: i32, sensitivity: f32) -> i32 {
+        if value.abs() <= self.dead_zone {
+            return 0;
+        }
+        let sign = if self.invert { -1.0 } else { 1.0 };
+        (value as f32 * self.sensitivity * sensitivity * sign).round() as i32
+    }
+}
+
+/// Device axes as spacenavd reports them, `x` to the right, `y` up and `z`
+/// towards the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Axes {
+    pub x: Axis,
+    pub y: Axis,
+    pub z: Axis,
+    pub rx: Axis,
+    pub ry: Axis,
+    pub rz: Axis,
+}
+
+/*
+[match]
+name = "Onshape"
+active_set = "Part Studio"
+
+sensitivity = 1.5
+dominant = false
+mode = "target"
+
+[axes.z]
+invert = true
+dead_zone = 20
+
+[buttons]
+0 = { command = "ID_FIT" }
+ */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// File name without extension
+    #[serde(skip)]
+    pub name: String,
+    #[serde(rename = "match")]
+    pub matches: Match,
+    /// Applies to all axes, on top of their own sensitivity
+    pub sensitivity: f32,
+    pub axes: Axes,
+    /// Only the axis deflected the most moves, the others are dropped
+    pub dominant: bool,
+    /// Navigation mode until a button picks another one
+    pub mode: Mode,
+    /// Overrides the configured buttons one by one
+    pub buttons: ButtonMap,
+}
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            name: String::new(),
+            matches: Match::default(),
+            sensitivity: 1.0,
+            axes: Axes::default(),
+            dominant: false,
+            mode: Mode::default(),
+            buttons: ButtonMap::empty(),
+        }
+    }
+}
+impl Profile {
+    /// Applies dead zones, sensitivity and inversion, then the dominant axis
+    pub fn apply(&self, motion: &spnav_event_motion) -> spnav_event_motion {
+        let axes = &self.axes;
+        let s = self.sensitivity;
+        let mut values = [
+            axes.x.apply(motion.x, s),
+            axes.y.apply(motion.y, s),
+            axes.z.apply(motion.z, s),
+            axes.rx.apply(motion.rx, s),
+            axes.ry.apply(motion.ry, s),
+            axes.rz.apply(motion.rz, s),
+        ];
+        if self.dominant {
+            let dominant = (0..6).max_by_key(|i| values[*i].abs()).unwrap_or(0);
+            for (i, value) in values.iter_mut().enumerate() {
+                if i != dominant {
+                    *value = 0;
+                }
+            }
+        }
+        spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            x: values[0],
+            y: values[1],
+            z: values[2],
+            rx: values[3],
+            ry: values[4],
+            rz: values[5],
+            period: motion.period,
+        }
+    }
+}
+
+/// All profiles of the profile directory, ordered by file name
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    profiles: Vec<Arc<Profile>>,
+}
+impl Profiles {
+    /// The profile matching the most fields of the client. On a tie the
+    /// first file name wins.
+    pub fn select(&self, name: Option<&str>, active_set: Option<&str>) -> Option<Arc<Profile>> {
+        let mut best: Option<(usize, &Arc<Profile>)> = None;
+        for profile in &self.profiles {
+            if let Some(specificity) = profile.matches.specificity(name, active_set) {
+                if best.is_none_or(|(best, _)| specificity > best) {
+                    best = Some((specificity, profile));
+                }
+            }
+        }
+        best.map(|(_, profile)| profile.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.profiles.len()
+    }
+}
+
+/// Reads one profile, named after its file
+fn load_file(path: &Path) -> Result<Profile, String> {
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut profile: Profile = config::parse_file(path, &text)?;
+    profile.name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(profile)
+}
+
+/// `.toml` and `.json` files of the directory, sorted by name
+fn profile_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext == "toml" || ext == "json")
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Loads every profile of the directory. Broken files are logged and left
+/// out, a missing directory means no profiles.
+pub fn load_dir(dir: &Path) -> Profiles {
+    let profiles = profile_files(dir)
+        .iter()
+        .filter_map(|path| match load_file(path) {
+            Ok(profile) => Some(Arc::new(profile)),
+            Err(err) => {
+                tracing::warn!(path = %path.display(), %err, "invalid profile");
+                None
+            }
+        })
+        .collect();
+    Profiles { profiles }
+}
+
+/// What changes when a profile is edited, added or removed
+fn fingerprint(dir: &Path) -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+    profile_files(dir)
+        .into_iter()
+        .map(|path| {
+            let meta = fs::metadata(&path).ok();
+            let modified = meta.as_ref().and_then(|meta| meta.modified().ok());
+            let len = meta.map_or(0, |meta| meta.len());
+            (path, modified, len)
+        })
+        .collect()
+}
+
+/// Runs a directory scan on the blocking pool, so a slow disk does not
+/// stall the sessions. None if the scan panicked.
+async fn scan<T: Send + 'static>(dir: &Path, f: fn(&Path) -> T) -> Option<T> {
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || f(&dir)).await.ok()
+}
+
+/// Reloads the profiles whenever a file in the directory changes
+pub async fn watch(dir: PathBuf, profiles: watch::Sender<Arc<Profiles>>) {
+    let mut last = scan(&dir, fingerprint).await;
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let current = scan(&dir, fingerprint).await;
+        if current == last {
+            continue;
+        }
+        last = current;
+        let loaded = match scan(&dir, load_dir).await {
+            Some(loaded) => loaded,
+            None => continue,
+        };
+        tracing::info!(dir = %dir.display(), count = loaded.len(), "profiles reloaded");
+        if profiles.send(Arc::new(loaded)).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{Binding, VirtualKey};
+
+    fn motion(values: [i32; 6]) -> spnav_event_motion {
+        spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            x: values[0],
+            y: values[1],
+            z: values[2],
+            rx: values[3],
+            ry: values[4],
+            rz: values[5],
+            period: 16,
+        }
+    }
+
+    #[test]
+    fn axes() {
+        let profile: Profile = toml::from_str(
+            "sensitivity = 2.0\n[axes.x]\ndead_zone = 10\n[axes.z]\ninvert = true\nsensitivity = 0.5\n",
+        )
+        .unwrap();
+        let out = profile.apply(&motion([10, 1, 100, 0, 0, -3]));
+        assert_eq!(out, motion([0, 2, -100, 0, 0, -6]));
+
+        let dominant = Profile {
+            dominant: true,
+            ..Profile::default()
+        };
+        assert_eq!(
+            dominant.apply(&motion([10, -40, 30, 0, 5, 0])),
+            motion([0, -40, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn most_specific_wins() {
+        let dir =
+            std::env::temp_dir().join(format!("spacenav-web-profiles-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a-default.toml"), "sensitivity = 0.5\n").unwrap();
+        fs::write(
+            dir.join("onshape.toml"),
+            "[match]\nname = \"onshape\"\n[buttons]\n0 = \"top\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("onshape-drawing.json"),
+            r#"{"match":{"name":"Onshape","active_set":"Drawing"},"dominant":true,"mode":"fly"}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("broken.toml"), "sensitivity = \"high\"\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a profile").unwrap();
+
+        let profiles = load_dir(&dir);
+        assert_eq!(profiles.len(), 3);
+
+        let name = |name: Option<&str>, set: Option<&str>| {
+            profiles.select(name, set).map(|p| p.name.clone())
+        };
+        assert_eq!(name(None, None).as_deref(), Some("a-default"));
+        assert_eq!(name(Some("Viewer"), None).as_deref(), Some("a-default"));
+        assert_eq!(
+            name(Some("Onshape"), Some("Part Studio")).as_deref(),
+            Some("onshape")
+        );
+        assert_eq!(
+            name(Some("Onshape"), Some("Drawing")).as_deref(),
+            Some("onshape-drawing")
+        );
+
+        let drawing = profiles.select(Some("Onshape"), Some("Drawing")).unwrap();
+        assert_eq!(drawing.mode, Mode::Fly);
+
+        let onshape = profiles.select(Some("Onshape"), None).unwrap();
+        assert_eq!(onshape.mode, Mode::Object);
+        assert_eq!(onshape.buttons.get(0), Some(&Binding::Key(VirtualKey(3))));
+        assert_eq!(onshape.buttons.get(1), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(load_dir(&dir).len(), 0);
+    }
+}
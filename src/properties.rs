@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::{matrix::Matrix, vector::Vector};
+
+/// Axis aligned box sent as `[minX, minY, minZ, maxX, maxY, maxZ]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extents {
+    pub min: Vector,
+    pub max: Vector,
+}
+impl Extents {
+    pub fn center(&self) -> Vector {
+        [
+            (self.min[0] + self.max[0]) * 0.5,
+            (self.min[1] + self.max[1]) * 0.5,
+            (self.min[2] + self.max[2]) * 0.5,
+        ]
+    }
+}
+
+/// Viewing volume sent as `[left, right, bottom, top, near, far]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub left: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub top: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PropertyError {
+    pub key: String,
+    pub expected: &'static str,
+}
+
+/// Everything the client told us about its view, model and selection, either
+/// pushed via `3dx_rpc:update` or answered to a `self:read`
+#[derive(Debug, Clone, Default)]
+pub struct Properties {
+    pub view_affine: Option<Matrix>,
+    pub view_target: Option<Vector>,
+    pub view_perspective: Option<bool>,
+    pub view_fov: Option<f32>,
+    pub view_extents: Option<Extents>,
+    pub view_frustum: Option<Frustum>,
+    pub model_extents: Option<Extents>,
+    pub selection_empty: Option<bool>,
+    pub selection_extents: Option<Extents>,
+    pub pivot_position: Option<Vector>,
+    pub pivot_visible: Option<bool>,
+    /// The user placed the pivot in the application, `pivot.position` is theirs
+    pub pivot_user: Option<bool>,
+    pub coordinate_system: Option<Matrix>,
+    pub frame_time: Option<f64>,
+    pub focus: Option<bool>,
+    pub hit_lookfrom: Option<Vector>,
+    pub hit_direction: Option<Vector>,
+    pub hit_aperture: Option<f32>,
+    pub hit_selection_only: Option<bool>,
+    pub hit_lookat: Option<Vector>,
+    /// Properties without a typed representation, e.g. `commands.activeSet`
+    pub other: HashMap<String, Value>,
+}
+
+fn floats<const N: usize>(key: &str, value: &Value) -> Result<[f32; N], PropertyError> {
+    let err = || PropertyError {
+        key: key.to_string(),
+        expected: "an array of numbers",
+    };
+    let array = value.as_array().ok_or_else(err)?;
+    if array.len() != N {
+        return Err(err());
+    }
+    let mut out = [0.0; N];
+    for (o, v) in out.iter_mut().zip(array) {
+        *o = v.as_f64().ok_or_else(err)? as f32;
+    }
+    Ok(out)
+}
+
+fn number(key: &str, value: &Value) -> Result<f64, PropertyError> {
+    value.as_f64().ok_or_else(|| PropertyError {
+        key: key.to_string(),
+        expected: "a number",
+    })
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, PropertyError> {
+    value.as_bool().ok_or_else(|| PropertyError {
+        key: key.to_string(),
+        expected: "a boolean",
+    })
+}
+
+fn extents(key: &str, value: &Value) -> Result<Extents, PropertyError> {
+    let v: [f32; 6] = floats(key, value)?;
+    Ok(Extents {
+        min: [v[0], v[1], v[2]],
+        max: [v[3], v[4], v[5]],
+    })
+}
+
+fn frustum(key: &str, value: &Value) -> Result<Frustum, PropertyError> {
+    let v: [f32; 6] = floats(key, value)?;
+    Ok(Frustum {
+        left: v[0],
+        right: v[1],
+        bottom: v[2],
+        top: v[3],
+        near: v[4],
+        far: v[5],
+    })
+}
+
+/// Large payloads kept by `Commands` instead
+fn owned_by_commands(key: &str) -> bool {
+    key == "images" || (key.starts_with("commands.") && key != "commands.activeSet")
+}
+
+fn extents_value(e: &Extents) -> Value {
+    json!([e.min[0], e.min[1], e.min[2], e.max[0], e.max[1], e.max[2]])
+}
+
+impl Properties {
+    /// Stores a single property by its dotted name
+    pub fn set(&mut self, key: &str, value: &Value) -> Result<(), PropertyError> {
+        match key {
+            "view.affine" => self.view_affine = Some(floats(key, value)?),
+            "view.target" => self.view_target = Some(floats(key, value)?),
+            "view.perspective" => self.view_perspective = Some(boolean(key, value)?),
+            "view.fov" => self.view_fov = Some(number(key, value)? as f32),
+            "view.extents" => self.view_extents = Some(extents(key, value)?),
+            "view.frustum" => self.view_frustum = Some(frustum(key, value)?),
+            "model.extents" => self.model_extents = Some(extents(key, value)?),
+            "selection.empty" => self.selection_empty = Some(boolean(key, value)?),
+            "selection.extents" => self.selection_extents = Some(extents(key, value)?),
+            "pivot.position" => self.pivot_position = Some(floats(key, value)?),
+            "pivot.visible" => self.pivot_visible = Some(boolean(key, value)?),
+            "pivot.user" => self.pivot_user = Some(boolean(key, value)?),
+            "coordinateSystem" => self.coordinate_system = Some(floats(key, value)?),
+            "frame.time" => self.frame_time = Some(number(key, value)?),
+            "focus" => self.focus = Some(boolean(key, value)?),
+            "hit.lookfrom" => self.hit_lookfrom = Some(floats(key, value)?),
+            "hit.direction" => self.hit_direction = Some(floats(key, value)?),
+            "hit.aperture" => self.hit_aperture = Some(number(key, value)? as f32),
+            "hit.selectionOnly" => self.hit_selection_only = Some(boolean(key, value)?),
+            // The application answers null when nothing was hit
+            "hit.lookat" if value.is_null() => self.hit_lookat = None,
+            "hit.lookat" => self.hit_lookat = Some(floats(key, value)?),
+            _ => {
+                self.other.insert(key.to_string(), value.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores every property of a `3dx_rpc:update` payload. Nested objects
+    /// such as `{"frame":{"time":1}}` are flattened to `frame.time`. The
+    /// command tree and images are left to `Commands`, only
+    /// `commands.activeSet` is kept here.
+    pub fn update(&mut self, map: &Map<String, Value>) -> Result<(), PropertyError> {
+        self.update_prefixed("", map)
+    }
+
+    fn update_prefixed(
+        &mut self,
+        prefix: &str,
+        map: &Map<String, Value>,
+    ) -> Result<(), PropertyError> {
+        for (name, value) in map {
+            let key = format!("{prefix}{name}");
+            if owned_by_commands(&key) {
+                continue;
+            }
+            match value {
+                Value::Object(nested) => self.update_prefixed(&format!("{key}."), nested)?,
+                _ => self.set(&key, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the last known value of a property in its wire format
+    pub fn get(&self, key: &str) -> Option<Value> {
+        match key {
+            "view.affine" => self.view_affine.map(|v| json!(v)),
+            "view.target" => self.view_target.map(|v| json!(v)),
+            "view.perspective" => self.view_perspective.map(|v| json!(v)),
+            "view.fov" => self.view_fov.map(|v| json!(v)),
+            "view.extents" => self.view_extents.as_ref().map(extents_value),
+            "view.frustum" => self
+                .view_frustum
+                .map(|f| json!([f.left, f.right, f.bottom, f.top, f.near, f.far])),
+            "model.extents" => self.model_extents.as_ref().map(extents_value),
+            "selection.empty" => self.selection_empty.map(|v| json!(v)),
+            "selection.extents" => self.selection_extents.as_ref().map(extents_value),
+            "pivot.position" => self.pivot_position.map(|v| json!(v)),
+            "pivot.visible" => self.pivot_visible.map(|v| json!(v)),
+            "pivot.user" => self.pivot_user.map(|v| json!(v)),
+            "coordinateSystem" => self.coordinate_system.map(|v| json!(v)),
+            "frame.time" => self.frame_time.map(|v| json!(v)),
+            "focus" => self.focus.map(|v| json!(v)),
+            "hit.lookfrom" => self.hit_lookfrom.map(|v| json!(v)),
+            "hit.direction" => self.hit_direction.map(|v| json!(v)),
+            "hit.aperture" => self.hit_aperture.map(|v| json!(v)),
+            "hit.selectionOnly" => self.hit_selection_only.map(|v| json!(v)),
+            "hit.lookat" => self.hit_lookat.map(|v| json!(v)),
+            _ => self.other.get(key).cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_flattens_nested() {
+        let mut props = Properties::default();
+        let update = json!({
+            "frame": { "time": 1234.5 },
+            "focus": true,
+            "commands": { "activeSet": "Part Studio" },
+            "view": { "perspective": false, "fov": 0.5 },
+        });
+        props.update(update.as_object().unwrap()).unwrap();
+
+        assert_eq!(props.frame_time, Some(1234.5));
+        assert_eq!(props.focus, Some(true));
+        assert_eq!(props.view_perspective, Some(false));
+        assert_eq!(props.view_fov, Some(0.5));
+        assert_eq!(props.get("commands.activeSet"), Some(json!("Part Studio")));
+    }
+
+    #[test]
+    fn leaves_commands_to_their_owner() {
+        let mut props = Properties::default();
+        let update = json!({
+            "commands": { "activeSet": "Drawing", "tree": { "nodes": [] } },
+            "commands.tree": [],
+            "images": [{ "id": "ID_FIT" }],
+        });
+        props.update(update.as_object().unwrap()).unwrap();
+
+        assert_eq!(props.get("commands.activeSet"), Some(json!("Drawing")));
+        assert_eq!(props.get("commands.tree"), None);
+        assert_eq!(props.get("images"), None);
+        assert_eq!(props.other.len(), 1);
+    }
+
+    #[test]
+    fn typed_round_trip() {
+        let mut props = Properties::default();
+        let extents = json!([-1.0, -2.0, -3.0, 1.0, 2.0, 3.0]);
+        props.set("model.extents", &extents).unwrap();
+
+        let model = props.model_extents.unwrap();
+        assert_eq!(model.min, [-1.0, -2.0, -3.0]);
+        assert_eq!(model.max, [1.0, 2.0, 3.0]);
+        assert_eq!(props.get("model.extents"), Some(extents));
+
+        props.set("hit.lookat", &json!([1.0, 2.0, 3.0])).unwrap();
+        props.set("hit.lookat", &Value::Null).unwrap();
+        assert_eq!(props.hit_lookat, None);
+    }
+
+    #[test]
+    fn rejects_wrong_types() {
+        let mut props = Properties::default();
+        assert_eq!(
+            props.set("view.affine", &json!([1.0, 2.0])),
+            Err(PropertyError {
+                key: "view.affine".to_string(),
+                expected: "an array of numbers",
+            })
+        );
+        assert!(props.set("focus", &json!("yes")).is_err());
+        assert_eq!(props.focus, None);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{
+    commands::Commands,
+    spnav::{spnav_event, spnav_event_motion, SPNAV_EVENT_MOTION},
+};
+
+/// What a session's client told about itself, for the admin API
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SessionInfo {
+    /// `name` from the 3dcontroller create arguments
+    pub name: Option<String>,
+    /// Profile applied to the device, by file name
+    pub profile: Option<String>,
+    pub commands: Commands,
+}
+
+struct Entry {
+    events: mpsc::UnboundedSender<spnav_event>,
+    info: SessionInfo,
+}
+
+/// All live controller sessions, across browser tabs. Device events only go
+/// to the session whose client reported `focus: true` last.
+#[derive(Default)]
+pub struct Registry {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    sessions: HashMap<u32, Entry>,
+    focused: Option<u32>,
+    /// Live sessions in the order they were focused, most recent last. Those
+    /// never focused come first.
+    history: Vec<u32>,
+}
+impl Inner {
+    fn send(&self, instance: u32, event: spnav_event) {
+        if let Some(entry) = self.sessions.get(&instance) {
+            let _ = entry.events.send(event);
+        }
+    }
+
+    /// Brings a session which loses the device to rest, it would keep
+    /// integrating the last deflection otherwise
+    fn release(&self, instance: u32) {
+        self.send(
+            instance,
+            spnav_event::Motion(spnav_event_motion {
+                event_type: SPNAV_EVENT_MOTION,
+                ..Default::default()
+            }),
+        );
+    }
+
+    fn change_focus(&mut self, focused: Option<u32>) {
+        if self.focused == focused {
+            return;
+        }
+        if let Some(old) = self.focused {
+            self.release(old);
+        }
+        self.focused = focused;
+        tracing::info!(instance = ?focused, "focus changed");
+    }
+
+    /// Hands the focus to the most recently focused session except `instance`
+    fn fall_back(&mut self, instance: u32) {
+        let fallback = self.history.iter().rev().find(|i| **i != instance).copied();
+        self.change_focus(fallback);
+    }
+}
+
+impl Registry {
+    pub fn new() -> Arc<Registry> {
+        Arc::new(Registry::default())
+    }
+
+    /// Adds a session and returns the channel its device events arrive on.
+    /// The first session gets the focus until a client claims it.
+    pub fn register(&self, instance: u32) -> mpsc::UnboundedReceiver<spnav_event> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut inner = self.inner.lock().unwrap();
+        inner.sessions.insert(
+            instance,
+            Entry {
+                events: tx,
+                info: SessionInfo::default(),
+            },
+        );
+        if inner.focused.is_none() {
+            inner.history.push(instance);
+            inner.change_focus(Some(instance));
+        } else {
+            inner.history.insert(0, instance);
+        }
+        rx
+    }
+
+    /// Removes a closed session. If it had the focus, the most recently
+    /// focused session still alive takes over.
+    pub fn unregister(&self, instance: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.sessions.remove(&instance);
+        inner.history.retain(|i| *i != instance);
+        if inner.focused == Some(instance) {
+            // Nothing to bring to rest, the session is gone
+            inner.focused = None;
+            inner.fall_back(instance);
+        }
+    }
+
+    /// A session losing the focus hands it to the one focused before, as
+    /// if it had been closed
+    pub fn set_focus(&self, instance: u32, focus: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.sessions.contains_key(&instance) {
+            return;
+        }
+        if focus {
+            inner.history.retain(|i| *i != instance);
+            inner.history.push(instance);
+            inner.change_focus(Some(instance));
+        } else if inner.focused == Some(instance) {
+            inner.history.retain(|i| *i != instance);
+            inner.history.insert(0, instance);
+            inner.fall_back(instance);
+        }
+    }
+
+    /// Changes the published info of a live session
+    pub fn update_info<T>(
+        &self,
+        instance: u32,
+        f: impl FnOnce(&mut SessionInfo) -> T,
+    ) -> Option<T> {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .sessions
+            .get_mut(&instance)
+            .map(|entry| f(&mut entry.info))
+    }
+
+    /// Looks into a session's info under the lock, without cloning it
+    pub fn with_info<T>(&self, instance: u32, f: impl FnOnce(&SessionInfo) -> T) -> Option<T> {
+        let inner = self.inner.lock().unwrap();
+        inner.sessions.get(&instance).map(|entry| f(&entry.info))
+    }
+
+    /// Instances of all live sessions and the one having the focus
+    pub fn instances(&self) -> (Vec<u32>, Option<u32>) {
+        let inner = self.inner.lock().unwrap();
+        let mut instances: Vec<u32> = inner.sessions.keys().copied().collect();
+        instances.sort_unstable();
+        (instances, inner.focused)
+    }
+
+    pub fn dispatch(&self, event: spnav_event) {
+        let inner = self.inner.lock().unwrap();
+        if let Some(focused) = inner.focused {
+            inner.send(focused, event);
+        }
+    }
+
+    /// Forwards device events to the focused session until the device task ends
+    pub async fn route(self: Arc<Registry>, mut device: broadcast::Receiver<spnav_event>) {
+        loop {
+            match device.recv().await {
+                Ok(event) => self.dispatch(event),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spnav::spnav_event_button;
+
+    impl Registry {
+        fn focused(&self) -> Option<u32> {
+            self.inner.lock().unwrap().focused
+        }
+    }
+
+    fn button(bnum: i32) -> spnav_event {
+        spnav_event::Button(spnav_event_button {
+            bnum,
+            press: true,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn routes_to_focused() {
+        let registry = Registry::new();
+        let mut a = registry.register(1);
+        let mut b = registry.register(2);
+        assert_eq!(registry.focused(), Some(1));
+
+        registry.dispatch(button(0));
+        assert_eq!(a.try_recv().unwrap(), button(0));
+        assert!(b.try_recv().is_err());
+
+        registry.set_focus(2, true);
+        // The old session is brought to rest
+        assert!(matches!(a.try_recv().unwrap(), spnav_event::Motion(m) if m.x == 0));
+
+        registry.dispatch(button(1));
+        assert!(a.try_recv().is_err());
+        assert_eq!(b.try_recv().unwrap(), button(1));
+
+        // Unfocused, the device goes back to the session focused before
+        registry.set_focus(2, false);
+        assert_eq!(registry.focused(), Some(1));
+        registry.dispatch(button(2));
+        assert_eq!(a.try_recv().unwrap(), button(2));
+        assert!(matches!(b.try_recv().unwrap(), spnav_event::Motion(_)));
+        assert!(b.try_recv().is_err());
+
+        // Even a session which gave up the focus takes it over again
+        registry.unregister(1);
+        assert_eq!(registry.focused(), Some(2));
+        // Nobody else to take it
+        registry.set_focus(2, false);
+        assert_eq!(registry.focused(), None);
+    }
+
+    #[test]
+    fn falls_back_to_last_focused() {
+        let registry = Registry::new();
+        let _a = registry.register(1);
+        let _b = registry.register(2);
+        let _c = registry.register(3);
+
+        registry.set_focus(2, true);
+        registry.set_focus(1, true);
+        registry.set_focus(3, true);
+        assert_eq!(registry.focused(), Some(3));
+
+        registry.unregister(3);
+        assert_eq!(registry.focused(), Some(1));
+
+        registry.unregister(2);
+        assert_eq!(registry.focused(), Some(1));
+
+        registry.unregister(1);
+        assert_eq!(registry.focused(), None);
+
+        let _d = registry.register(4);
+        assert_eq!(registry.focused(), Some(4));
+    }
+
+    #[test]
+    fn falls_back_to_default_focus() {
+        let registry = Registry::new();
+        // Focused by default, never by its client
+        let _a = registry.register(1);
+        let _b = registry.register(2);
+        registry.set_focus(2, true);
+
+        registry.unregister(2);
+        assert_eq!(registry.focused(), Some(1));
+    }
+}
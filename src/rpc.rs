@@ -0,0 +1,253 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::{generate_id, wamp};
+
+/// How long the client gets to answer a `self:read` or `self:update`
+pub const CALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcError {
+    /// The client answered with a CALLERROR
+    Failed { uri: String, desc: String },
+    /// The client did not answer within the timeout
+    Timeout,
+    /// The connection closed before the client answered
+    Closed,
+    /// The answer does not have the type of the property
+    InvalidValue(String),
+}
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Failed { uri, desc } => write!(f, "client error {uri}: {desc}"),
+            RpcError::Timeout => write!(f, "client did not answer in time"),
+            RpcError::Closed => write!(f, "connection closed"),
+            RpcError::InvalidValue(reason) => write!(f, "invalid value: {reason}"),
+        }
+    }
+}
+
+type Reply = Result<Value, RpcError>;
+
+/// Calls into the client's controller object, which the client answers with
+/// CALLRESULT or CALLERROR messages carrying the same call id.
+///
+/// Clones share the table of outstanding calls, so one clone can resolve
+/// replies while another awaits them.
+#[derive(Clone)]
+pub struct ClientRpc {
+    instance: u32,
+    calls: Arc<Mutex<Calls>>,
+}
+
+#[derive(Default)]
+struct Calls {
+    pending: HashMap<String, oneshot::Sender<Reply>>,
+    /// Calls given up on, their replies are late rather than unknown
+    timed_out: HashSet<String>,
+}
+
+/// A call which has been registered but not answered yet
+pub struct PendingCall {
+    id: String,
+    reply: oneshot::Receiver<Reply>,
+    calls: Arc<Mutex<Calls>>,
+}
+impl PendingCall {
+    pub async fn wait(self, timeout: Duration) -> Reply {
+        match tokio::time::timeout(timeout, self.reply).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => Err(RpcError::Closed),
+            Err(_) => {
+                let mut calls = self.calls.lock().unwrap();
+                calls.pending.remove(&self.id);
+                calls.timed_out.insert(self.id);
+                Err(RpcError::Timeout)
+            }
+        }
+    }
+}
+
+impl ClientRpc {
+    pub fn new(instance: u32) -> ClientRpc {
+        ClientRpc {
+            instance,
+            calls: Arc::default(),
+        }
+    }
+
+    pub fn instance(&self) -> u32 {
+        self.instance
+    }
+
+    /// Builds a call and registers it for a reply. The message still has to
+    /// be sent by the caller.
+    pub fn call(&self, method: &str, args: Vec<Value>) -> (wamp::Message, PendingCall) {
+        let id = generate_id();
+        let (tx, rx) = oneshot::channel();
+        self.calls.lock().unwrap().pending.insert(id.clone(), tx);
+
+        let msg = build_client_call(self.instance, &id, method, args);
+        let call = PendingCall {
+            id,
+            reply: rx,
+            calls: self.calls.clone(),
+        };
+        (msg, call)
+    }
+
+    pub fn read(&self, key: &str) -> (wamp::Message, PendingCall) {
+        self.call("self:read", vec![json!(""), json!(key)])
+    }
+
+    pub fn update(&self, key: &str, value: Value) -> (wamp::Message, PendingCall) {
+        self.call("self:update", vec![json!(""), json!(key), value])
+    }
+
+    /// Builds an update nobody waits for, e.g. the per frame `view.affine`
+    pub fn notify(&self, key: &str, value: Value) -> wamp::Message {
+        build_update_call(self.instance, &generate_id(), key, value)
+    }
+
+    /// Hands a CALLRESULT or CALLERROR to the call waiting for it. Replies
+    /// nobody waits for, e.g. to `notify`, are dropped. Returns false if the
+    /// message is not a reply or came after its call timed out.
+    pub fn resolve(&self, msg: &wamp::Message) -> bool {
+        let (call_id, reply) = match msg {
+            wamp::Message::CallResult { call_id, result } => (call_id, Ok(result.clone())),
+            wamp::Message::CallError {
+                call_id,
+                error_uri,
+                error_desc,
+                ..
+            } => (
+                call_id,
+                Err(RpcError::Failed {
+                    uri: error_uri.clone(),
+                    desc: error_desc.clone(),
+                }),
+            ),
+            _ => return false,
+        };
+
+        let mut calls = self.calls.lock().unwrap();
+        if let Some(tx) = calls.pending.remove(call_id) {
+            // The caller may have given up already
+            let _ = tx.send(reply);
+            return true;
+        }
+        !calls.timed_out.remove(call_id)
+    }
+
+    /// Fails every outstanding call, used when the connection goes away
+    pub fn close(&self) {
+        let mut calls = self.calls.lock().unwrap();
+        calls.pending.clear();
+        calls.timed_out.clear();
+    }
+}
+
+pub fn controller_topic(instance: u32) -> String {
+    format!("3dconnexion:3dcontroller/{}", instance)
+}
+
+/// Wraps a call to the client's controller object into an EVENT
+fn build_client_call(instance: u32, id: &str, method: &str, args: Vec<Value>) -> wamp::Message {
+    let call = wamp::Message::Call {
+        call_id: id.to_string(),
+        proc_uri: method.to_string(),
+        args,
+    };
+    wamp::Message::Event {
+        topic_uri: controller_topic(instance),
+        event: serde_json::to_value(call).expect("WAMP messages always serialize"),
+    }
+}
+
+/*
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"1cJSqNoRqbVxr4Ds","self:update","","hit.selectionOnly",false]]
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"DMr5ZjiANwTtk65Y","self:update","","settings.changed",2]]
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"AEZWTPeAMmbGLPIB","self:read","","coordinateSystem"]]
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"xqys5A4ZiD8E3lla","self:read","","selection.empty"]]
+ */
+fn build_update_call(instance: u32, id: &str, key: &str, value: Value) -> wamp::Message {
+    build_client_call(
+        instance,
+        id,
+        "self:update",
+        vec![json!(""), json!(key), value],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_id(msg: &wamp::Message) -> String {
+        match msg {
+            wamp::Message::Event { event, .. } => event[1].as_str().unwrap().to_string(),
+            _ => panic!("expected an event"),
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_by_id() {
+        let rpc = ClientRpc::new(42);
+        let (first, first_call) = rpc.read("view.affine");
+        let (second, second_call) = rpc.read("view.target");
+
+        assert_eq!(
+            wamp::Message::from_text(&first.to_text()).unwrap(),
+            wamp::Message::Event {
+                topic_uri: "3dconnexion:3dcontroller/42".to_string(),
+                event: json!([2, call_id(&first), "self:read", "", "view.affine"]),
+            }
+        );
+
+        assert!(rpc.resolve(&wamp::Message::call_error(
+            &call_id(&second),
+            "http://example.com/error",
+            "no target"
+        )));
+        assert!(rpc.resolve(&wamp::Message::call_result(&call_id(&first), json!([1.0]))));
+        // Answers to notifies are swallowed as well
+        let notify = rpc.notify("view.affine", json!([]));
+        assert!(rpc.resolve(&wamp::Message::call_result(&call_id(&notify), json!(null))));
+        assert!(!rpc.resolve(&wamp::Message::Prefix {
+            prefix: "self".to_string(),
+            uri: "http://example.com/".to_string(),
+        }));
+
+        assert_eq!(first_call.wait(CALL_TIMEOUT).await, Ok(json!([1.0])));
+        assert_eq!(
+            second_call.wait(CALL_TIMEOUT).await,
+            Err(RpcError::Failed {
+                uri: "http://example.com/error".to_string(),
+                desc: "no target".to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout_and_close() {
+        let rpc = ClientRpc::new(42);
+        let (msg, call) = rpc.update("motion", json!(true));
+        assert_eq!(
+            call.wait(Duration::from_millis(10)).await,
+            Err(RpcError::Timeout)
+        );
+        assert!(!rpc.resolve(&wamp::Message::call_result(&call_id(&msg), json!(null))));
+
+        let (_, call) = rpc.read("focus");
+        rpc.close();
+        assert_eq!(call.wait(CALL_TIMEOUT).await, Err(RpcError::Closed));
+    }
+}
@@ -0,0 +1,520 @@
+use warp::Filter;
+
+use crate::{
+    admin,
+    session::{self, ServerState},
+};
+
+/// The NL-Proxy endpoints: `/3dconnexion/nlproxy` tells the SDK which port
+/// to open the websocket on, `/` is the WAMP websocket itself. The admin
+/// API is served next to them.
+pub fn routes(
+    advertised_port: u16,
+    state: ServerState,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let admin = admin::routes(state.registry.clone());
+    let websocket = warp::path::end()
+        .and(warp::ws())
+        .and(warp::addr::remote())
+        .map(move |ws: warp::ws::Ws, remote| {
+            let state = state.clone();
+            // This will call our function if the handshake succeeds.
+            ws.on_upgrade(move |socket| session::handle_session(socket, remote, state))
+        })
+        .with(warp::reply::with::header("Sec-WebSocket-Protocol", "wamp"));
+
+    let advertised = serde_json::json!({ "port": advertised_port }).to_string();
+    let proxy = warp::path!("3dconnexion" / "nlproxy").map(move || advertised.clone());
+
+    proxy
+        .or(websocket)
+        .with(warp::reply::with::header(
+            "Access-Control-Allow-Origin",
+            "*",
+        ))
+        .or(admin)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use serde_json::json;
+    use tokio::{
+        sync::{broadcast, watch},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        client_replay,
+        keys::ButtonMap,
+        matrix::IDENTITY,
+        profile::Profiles,
+        registry::Registry,
+        spnav::{
+            spnav_event, spnav_event_button, spnav_event_motion, SPNAV_EVENT_BUTTON,
+            SPNAV_EVENT_MOTION,
+        },
+        test_client::FakeClient,
+        trace::{self, Direction, FrameTrace},
+    };
+
+    /// Runs a server on an ephemeral port, fed by the returned device channel
+    fn start() -> (String, broadcast::Sender<spnav_event>) {
+        let (url, device, _) = start_with(ButtonMap::default());
+        (url, device)
+    }
+
+    fn start_with(buttons: ButtonMap) -> (String, broadcast::Sender<spnav_event>, Arc<Registry>) {
+        serve(ServerState {
+            registry: Registry::new(),
+            trace: None,
+            buttons: Arc::new(buttons),
+            profiles: watch::channel(Arc::new(Profiles::default())).1,
+        })
+    }
+
+    fn serve(state: ServerState) -> (String, broadcast::Sender<spnav_event>, Arc<Registry>) {
+        let (device_tx, _) = broadcast::channel(64);
+        let registry = state.registry.clone();
+        tokio::spawn(registry.clone().route(device_tx.subscribe()));
+        let (addr, server) =
+            warp::serve(routes(8181, state)).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+        tokio::spawn(server);
+        (format!("ws://{addr}/"), device_tx, registry)
+    }
+
+    fn button(bnum: i32, press: bool) -> spnav_event {
+        spnav_event::Button(spnav_event_button {
+            event_type: SPNAV_EVENT_BUTTON,
+            press,
+            bnum,
+        })
+    }
+
+    fn push(x: i32) -> spnav_event {
+        spnav_event::Motion(spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            x,
+            period: 16,
+            ..Default::default()
+        })
+    }
+
+    fn turn(ry: i32) -> spnav_event {
+        spnav_event::Motion(spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            ry,
+            period: 16,
+            ..Default::default()
+        })
+    }
+
+    async fn client(url: &str, name: &str) -> FakeClient {
+        let mut client = FakeClient::connect(url).await;
+        client
+            .properties
+            .insert("view.affine".to_string(), json!(IDENTITY));
+        client
+            .properties
+            .insert("view.target".to_string(), json!([0.0, 0.0, -1.0]));
+        client
+            .properties
+            .insert("view.perspective".to_string(), json!(true));
+        client.open(name).await;
+        client
+    }
+
+    /// Waits until the session has handled the subscription, device events
+    /// are dropped before
+    async fn subscribed(client: &mut FakeClient) {
+        let topic = format!("3dconnexion:3dcontroller/{}", client.instance.unwrap());
+        client
+            .call("3dx_rpc:read", vec![json!(topic), json!("focus")])
+            .await
+            .unwrap();
+    }
+
+    /// Pushes the cap until the focused session asks its client to animate
+    async fn start_motion(client: &mut FakeClient, device: &broadcast::Sender<spnav_event>) {
+        deflect(client, device, push(200)).await;
+    }
+
+    /// Keeps sending `event` until the focused session asks its client to
+    /// animate
+    async fn deflect(
+        client: &mut FakeClient,
+        device: &broadcast::Sender<spnav_event>,
+        event: spnav_event,
+    ) {
+        device.send(event).unwrap();
+        let device = device.clone();
+        let pusher = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let _ = device.send(event);
+            }
+        });
+        assert_eq!(client.wait_update("motion").await, json!(true));
+        // Once stopped no push can come after the caller's next event
+        pusher.abort();
+        let _ = pusher.await;
+    }
+
+    #[tokio::test]
+    async fn navigates_focused_client() {
+        let (url, device) = start();
+        let mut client = client(&url, "test").await;
+        // Frames before any motion are left alone
+        client.frame(0.0).await;
+        assert!(client.updates.is_empty());
+
+        start_motion(&mut client, &device).await;
+        device.send(push(200)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.frame(1.0).await;
+
+        let affine = client.updates_of("view.affine");
+        assert_eq!(affine.len(), 1);
+        let affine: Vec<f64> = serde_json::from_value(affine[0].clone()).unwrap();
+        assert!(affine.iter().all(|v| v.is_finite()));
+        // Object mode: moving the model right moves the camera left
+        assert!(affine[12] < 0.0);
+        assert_eq!(affine[13..15], [0.0, 0.0]);
+
+        // Each frame is one transaction
+        let transactions = client.updates_of("transaction");
+        assert_eq!(transactions, [json!(1), json!(0)]);
+
+        // Released cap: the next frames end the motion
+        device.send(push(0)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.frame(2.0).await;
+        client.frame(3.0).await;
+        assert_eq!(client.updates_of("motion").last(), Some(&json!(false)));
+
+        // Without extents the camera orbits its target, marked while moving
+        assert_eq!(
+            client.updates_of("pivot.position"),
+            [json!([0.0, 0.0, -1.0])]
+        );
+        assert_eq!(
+            client.updates_of("pivot.visible"),
+            [json!(true), json!(false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn orthographic_zoom_scales_extents() {
+        let (url, device) = start();
+        let mut client = client(&url, "test").await;
+        client
+            .properties
+            .insert("view.perspective".to_string(), json!(false));
+        client.properties.insert(
+            "view.extents".to_string(),
+            json!([-2.0, -1.0, 0.0, 2.0, 1.0, 10.0]),
+        );
+        // Only answered when asked, the client never pushes it
+        client.properties.insert(
+            "view.frustum".to_string(),
+            json!([-2.0, 2.0, -1.0, 1.0, 0.0, 10.0]),
+        );
+
+        start_motion(&mut client, &device).await;
+        device
+            .send(spnav_event::Motion(spnav_event_motion {
+                event_type: SPNAV_EVENT_MOTION,
+                z: 500,
+                period: 16,
+                ..Default::default()
+            }))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.frame(1.0).await;
+
+        // Pulling the cap zooms in: the camera stays, the extents shrink
+        let extents: Vec<f64> =
+            serde_json::from_value(client.updates_of("view.extents")[0].clone()).unwrap();
+        assert!(extents[0] > -2.0 && extents[3] < 2.0);
+        assert!((extents[0] * 0.5 - extents[1]).abs() < 1e-6);
+        assert_eq!(extents[2..3], [0.0]);
+        assert_eq!(extents[5..], [10.0]);
+        let frustum: Vec<f64> =
+            serde_json::from_value(client.updates_of("view.frustum")[0].clone()).unwrap();
+        assert_eq!(frustum[0], extents[0]);
+        assert_eq!(frustum[3], extents[4]);
+        assert_eq!(frustum[4..], [0.0, 10.0]);
+        let affine: Vec<f64> =
+            serde_json::from_value(client.updates_of("view.affine")[0].clone()).unwrap();
+        assert_eq!(affine[14], 0.0);
+    }
+
+    #[tokio::test]
+    async fn only_focused_client_moves() {
+        let (url, device) = start();
+        let mut first = client(&url, "first").await;
+        let mut second = client(&url, "second").await;
+
+        start_motion(&mut second, &device).await;
+        // The reply comes after anything the first session sent before it
+        let topic = format!("3dconnexion:3dcontroller/{}", first.instance.unwrap());
+        let focus = first
+            .call("3dx_rpc:read", vec![json!(topic), json!("focus")])
+            .await;
+        assert_eq!(focus, Ok(json!(true)));
+        assert!(first.updates_of("motion").is_empty());
+
+        first.set_focus(true).await;
+        start_motion(&mut first, &device).await;
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_procedure() {
+        let (url, _device) = start();
+        let mut client = client(&url, "test").await;
+        assert_eq!(
+            client.call("3dx_rpc:destroy", vec![]).await,
+            Err("wss://127.51.68.120/3dconnexion/error#unknown-procedure".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_call() {
+        let (url, _device) = start();
+        let mut client = client(&url, "test").await;
+        assert_eq!(
+            client.call_raw("[2,\"0.x\",123]", "0.x").await,
+            Err("wss://127.51.68.120/3dconnexion/error#invalid-argument".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_trace() {
+        let path =
+            std::env::temp_dir().join(format!("spacenav-web-trace-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (url, _device, _) = serve(ServerState {
+            registry: Registry::new(),
+            trace: Some(FrameTrace::open(&path).unwrap()),
+            buttons: Arc::new(ButtonMap::default()),
+            profiles: watch::channel(Arc::new(Profiles::default())).1,
+        });
+
+        let mut recorded = client(&url, "recorded").await;
+        subscribed(&mut recorded).await;
+        assert!(recorded.call("3dx_rpc:destroy", vec![]).await.is_err());
+        drop(recorded);
+
+        let entries = trace::load(&path).unwrap();
+        let frames = client_replay::session_frames(&entries, None).unwrap();
+        let sent = frames.iter().filter(|f| f.dir == Direction::In).count();
+        let report = client_replay::replay(&frames, &url).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Same handshake with a new instance and new server call ids
+        assert_eq!(report.sent, sent);
+        assert_eq!(report.mismatches, Vec::<String>::new());
+        assert_eq!(report.call_differences, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn buttons_send_keys() {
+        let (url, device) = start();
+        let mut client = client(&url, "test").await;
+        subscribed(&mut client).await;
+
+        for (bnum, press) in [(2, true), (1, true), (1, false)] {
+            device.send(button(bnum, press)).unwrap();
+        }
+        // Button 2 is not mapped, button 1 is Fit
+        assert_eq!(client.wait_update("keyRelease").await, json!(2));
+        assert_eq!(
+            client.updates,
+            [
+                ("keyPress".to_string(), json!(2)),
+                ("keyRelease".to_string(), json!(2)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn hit_test_picks_pivot() {
+        let (url, device) = start();
+        let mut client = client(&url, "test").await;
+        client
+            .properties
+            .insert("hit.lookat".to_string(), json!([1.0, 2.0, -3.0]));
+        // Only answered when asked, the client never pushes it
+        client
+            .properties
+            .insert("view.fov".to_string(), json!(std::f32::consts::FRAC_PI_2));
+
+        // Panning does not need a pivot
+        start_motion(&mut client, &device).await;
+        assert!(client.updates_of("hit.lookfrom").is_empty());
+        device.send(push(0)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.frame(1.0).await;
+        client.frame(2.0).await;
+        assert_eq!(client.updates_of("motion").last(), Some(&json!(false)));
+
+        // Read again when motion starts, so this undoes the pan
+        client
+            .properties
+            .insert("view.affine".to_string(), json!(IDENTITY));
+        deflect(&mut client, &device, turn(200)).await;
+        assert_eq!(client.updates_of("hit.lookfrom"), [json!([0.0, 0.0, 0.0])]);
+        assert_eq!(
+            client.updates_of("hit.direction"),
+            [json!([0.0, 0.0, -1.0])]
+        );
+        // 90 degrees wide one unit from the target
+        let aperture = client.updates_of("hit.aperture")[0].as_f64().unwrap();
+        assert!((aperture - 0.02).abs() < 1e-6);
+        assert_eq!(client.updates_of("hit.selectionOnly"), [json!(false)]);
+        assert_eq!(
+            client.updates_of("pivot.position").last(),
+            Some(&json!([1.0, 2.0, -3.0]))
+        );
+
+        device.send(turn(0)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.frame(3.0).await;
+        client.frame(4.0).await;
+        assert_eq!(client.updates_of("motion").last(), Some(&json!(false)));
+
+        // Nothing hit: back to the target
+        client
+            .properties
+            .insert("hit.lookat".to_string(), serde_json::Value::Null);
+        deflect(&mut client, &device, turn(200)).await;
+        assert_eq!(
+            client.updates_of("pivot.position").last(),
+            Some(&json!([0.0, 0.0, -1.0]))
+        );
+    }
+
+    #[tokio::test]
+    async fn user_pivot_is_read() {
+        let (url, device) = start();
+        let mut client = client(&url, "test").await;
+        // Only answered when asked, the client never pushes them
+        client
+            .properties
+            .insert("pivot.user".to_string(), json!(true));
+        client
+            .properties
+            .insert("pivot.position".to_string(), json!([3.0, 0.0, -4.0]));
+
+        start_motion(&mut client, &device).await;
+        assert_eq!(
+            client.updates_of("pivot.position"),
+            [json!([3.0, 0.0, -4.0])]
+        );
+        // The user's pivot wins over what is under the screen centre
+        assert!(client.updates_of("hit.lookfrom").is_empty());
+    }
+
+    #[tokio::test]
+    async fn mode_button() {
+        let buttons = toml::from_str("0 = { mode = \"camera\" }").unwrap();
+        let (url, device, _) = start_with(buttons);
+        let mut client = client(&url, "test").await;
+        subscribed(&mut client).await;
+
+        device.send(button(0, true)).unwrap();
+        start_motion(&mut client, &device).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.frame(1.0).await;
+
+        let affine: Vec<f64> =
+            serde_json::from_value(client.updates_of("view.affine")[0].clone()).unwrap();
+        assert!(affine[12] > 0.0);
+    }
+
+    #[tokio::test]
+    async fn target_mode_moves_target() {
+        let buttons = toml::from_str("0 = { mode = \"target\" }").unwrap();
+        let (url, device, _) = start_with(buttons);
+        let mut client = client(&url, "test").await;
+        subscribed(&mut client).await;
+
+        device.send(button(0, true)).unwrap();
+        start_motion(&mut client, &device).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.frame(1.0).await;
+
+        // Panned along with the camera, in the same transaction
+        let target: Vec<f64> =
+            serde_json::from_value(client.updates_of("view.target")[0].clone()).unwrap();
+        assert!(target[0] < 0.0);
+        assert_eq!(target[1..], [0.0, -1.0]);
+        let keys: Vec<&str> = client.updates.iter().map(|(k, _)| k.as_str()).collect();
+        let end = keys.iter().rposition(|k| *k == "transaction").unwrap();
+        let target_at = keys.iter().position(|k| *k == "view.target").unwrap();
+        assert!(target_at < end);
+
+        // The next motion orbits the moved target
+        device.send(push(0)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.frame(2.0).await;
+        client.frame(3.0).await;
+        assert_eq!(client.updates_of("motion").last(), Some(&json!(false)));
+        let moved = client.updates_of("view.target").last().cloned().unwrap();
+        start_motion(&mut client, &device).await;
+        assert_eq!(client.updates_of("pivot.position").last(), Some(&moved));
+    }
+
+    #[tokio::test]
+    async fn command_tree_and_bound_button() {
+        let buttons = toml::from_str("0 = { command = \"ID_FIT\" }").unwrap();
+        let (url, device, registry) = start_with(buttons);
+        let mut client = client(&url, "modeler").await;
+        let instance = client.instance.unwrap();
+        client
+            .update(json!({ "commands": {
+                "activeSet": "VIEW",
+                "tree": { "nodes": [{ "id": "VIEW", "label": "View", "nodes": [
+                    { "id": "ID_FIT", "label": "Fit" },
+                ] }] },
+            } }))
+            .await
+            .unwrap();
+
+        let admin = admin::routes(registry);
+        let reply = warp::test::request()
+            .path("/admin/sessions")
+            .reply(&admin)
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(reply.body()).unwrap(),
+            json!([{
+                "activeSet": "VIEW",
+                "focused": true,
+                "instance": instance,
+                "name": "modeler",
+                "profile": null,
+            }])
+        );
+        let reply = warp::test::request()
+            .path(&format!("/admin/sessions/{instance}/commands"))
+            .reply(&admin)
+            .await;
+        let commands: serde_json::Value = serde_json::from_slice(reply.body()).unwrap();
+        assert_eq!(commands["sets"][0]["nodes"][0]["id"], json!("ID_FIT"));
+        let reply = warp::test::request()
+            .path(&format!("/admin/sessions/{}/commands", instance + 1))
+            .reply(&admin)
+            .await;
+        assert_eq!(reply.status(), 404);
+
+        device.send(button(0, true)).unwrap();
+        device.send(button(0, false)).unwrap();
+        assert_eq!(
+            client.wait_update("commands.activeCommand").await,
+            json!("ID_FIT")
+        );
+    }
+}
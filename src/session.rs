@@ -0,0 +1,880 @@
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use rand::{thread_rng, Rng};
+use std::{net::SocketAddr, sync::Arc};
+
+use serde_json::{json, Value};
+use tokio::{
+    sync::{mpsc, watch},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, trace, warn, Instrument};
+use warp::ws::{Message, WebSocket};
+
+use crate::{
+    generate_id,
+    keys::{Binding, ButtonMap},
+    matrix::IDENTITY,
+    motion::MotionAccumulator,
+    navigation::{self, Mode, Projection, Scene, View},
+    profile::{Profile, Profiles},
+    properties::{Properties, PropertyError},
+    registry::Registry,
+    rpc::{ClientRpc, RpcError, CALL_TIMEOUT},
+    spnav::{spnav_event, spnav_event_button},
+    trace::{Direction, FrameTrace},
+    vector::Vector,
+    wamp,
+};
+
+/// Namespace the 3Dconnexion JS SDK registers for the `3dx_rpc` prefix
+const RPC_NAMESPACE: &str = "wss://127.51.68.120/3dconnexion#";
+/// Namespace of the error URIs in our CALLERROR replies
+const ERROR_NAMESPACE: &str = "wss://127.51.68.120/3dconnexion/error#";
+
+/// Grace period for the writer to flush queued frames on shutdown
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// State shared by all sessions of a server
+#[derive(Clone)]
+pub struct ServerState {
+    pub registry: Arc<Registry>,
+    /// Where every WAMP frame is dumped, if enabled
+    pub trace: Option<Arc<FrameTrace>>,
+    pub buttons: Arc<ButtonMap>,
+    pub profiles: watch::Receiver<Arc<Profiles>>,
+}
+
+struct Session {
+    outgoing: mpsc::UnboundedSender<wamp::Message>,
+    rpc: ClientRpc,
+    /// Camera being navigated, written back to the client every frame
+    view: View,
+    /// Mode picked with a button, overriding the profile's
+    mode: Option<Mode>,
+    /// Where the client was told to draw the pivot, None while hidden
+    pivot_shown: Option<Vector>,
+    /// Cleared once the client failed a hit test, it is not asked again
+    hit_testing: bool,
+    transactions: u32,
+    registry: Arc<Registry>,
+    /// Device events, only delivered while this session has the focus
+    device: mpsc::UnboundedReceiver<spnav_event>,
+    buttons: Arc<ButtonMap>,
+    profiles: watch::Receiver<Arc<Profiles>>,
+    /// `name` from the create arguments, profiles are selected by it
+    name: Option<String>,
+    /// Profile selected for the last device event
+    profile_name: Option<String>,
+    motion: MotionAccumulator,
+    /// The client has been told `motion: true` and is sending frames
+    moving: bool,
+    subscribed: bool,
+    prefixes: wamp::Prefixes,
+    properties: Properties,
+}
+impl Session {
+    fn new(
+        rpc: ClientRpc,
+        outgoing: mpsc::UnboundedSender<wamp::Message>,
+        registry: Arc<Registry>,
+        device: mpsc::UnboundedReceiver<spnav_event>,
+        buttons: Arc<ButtonMap>,
+        profiles: watch::Receiver<Arc<Profiles>>,
+    ) -> Session {
+        Session {
+            outgoing,
+            rpc,
+            view: View {
+                affine: IDENTITY,
+                center: None,
+                extents: None,
+                frustum: None,
+            },
+            mode: None,
+            pivot_shown: None,
+            hit_testing: true,
+            transactions: 1,
+            registry,
+            device,
+            buttons,
+            profiles,
+            name: None,
+            profile_name: None,
+            motion: MotionAccumulator::new(Instant::now()),
+            moving: false,
+            subscribed: false,
+            prefixes: default_prefixes(),
+            properties: Properties::default(),
+        }
+    }
+
+    /// Queues a message for the writer task
+    fn send(&self, msg: wamp::Message) {
+        if self.outgoing.send(msg).is_err() {
+            debug!("outgoing queue closed, message dropped");
+        }
+    }
+
+    /// Sends an update without waiting for the client to acknowledge it
+    fn send_update(&self, key: &str, value: Value) {
+        self.send(self.rpc.notify(key, value));
+    }
+
+    /// Reads a property from the client and stores it
+    async fn read(&mut self, key: &str) -> Result<Value, RpcError> {
+        let (msg, call) = self.rpc.read(key);
+        self.send(msg);
+        let value = call.wait(CALL_TIMEOUT).await?;
+        self.properties.set(key, &value).map_err(|err| {
+            RpcError::InvalidValue(format!("{} must be {}", err.key, err.expected))
+        })?;
+        Ok(value)
+    }
+
+    /// Like `read`, but a client which cannot provide the property is not an
+    /// error. Only a dead connection is.
+    async fn read_optional(&mut self, key: &str) -> Result<(), RpcError> {
+        match self.read(key).await {
+            Ok(_) => Ok(()),
+            Err(err @ (RpcError::Failed { .. } | RpcError::InvalidValue(_))) => {
+                debug!(key, %err, "client cannot provide property");
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes a property to the client and waits for it to be applied
+    async fn update(&mut self, key: &str, value: Value) -> Result<Value, RpcError> {
+        let (msg, call) = self.rpc.update(key, value.clone());
+        self.send(msg);
+        let ret = call.wait(CALL_TIMEOUT).await?;
+        if let Err(err) = self.properties.set(key, &value) {
+            warn!(key = %err.key, expected = err.expected, "invalid property");
+        }
+        Ok(ret)
+    }
+
+    /// The profile for the client's name and active set, logged when another
+    /// one takes over
+    fn profile(&mut self) -> Option<Arc<Profile>> {
+        let active_set = self.properties.get("commands.activeSet");
+        let profile = self.profiles.borrow().select(
+            self.name.as_deref(),
+            active_set.as_ref().and_then(Value::as_str),
+        );
+        let name = profile.as_ref().map(|profile| profile.name.clone());
+        if name != self.profile_name {
+            info!(profile = ?name, "profile selected");
+            self.profile_name = name.clone();
+            self.registry
+                .update_info(self.rpc.instance(), |info| info.profile = name);
+        }
+        profile
+    }
+
+    fn mode(&mut self) -> Mode {
+        match self.mode {
+            Some(mode) => mode,
+            None => self
+                .profile()
+                .map(|profile| profile.mode)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Restarts navigation from the camera and pivot the client reported last
+    fn reset_camera(&mut self) {
+        if let Some(view_affine) = self.properties.view_affine {
+            self.view.affine = view_affine;
+        }
+        self.view.extents = self.properties.view_extents;
+        self.view.frustum = self.properties.view_frustum;
+        let mode = self.mode();
+        self.view.center = navigation::center(mode, &self.properties);
+    }
+
+    /// Shows the pivot marker at the centre of rotation while moving and
+    /// hides it otherwise. Only changes are sent.
+    fn show_pivot(&mut self) {
+        let shown = if self.moving { self.view.center } else { None };
+        if shown == self.pivot_shown {
+            return;
+        }
+        if let Some(position) = shown {
+            self.send_update("pivot.position", json!(position));
+        }
+        if shown.is_some() != self.pivot_shown.is_some() {
+            self.send_update("pivot.visible", json!(shown.is_some()));
+        }
+        self.pivot_shown = shown;
+    }
+
+    /// Navigation task: owns all session state, handles client messages and
+    /// device events until either source goes away
+    async fn run(mut self, mut incoming: mpsc::UnboundedReceiver<wamp::Message>) {
+        loop {
+            tokio::select! {
+                msg = incoming.recv() => {
+                    match msg {
+                        Some(msg) => handle_msg(msg, &mut self).await,
+                        None => break,
+                    }
+                }
+                event = self.device.recv() => {
+                    match event {
+                        Some(event) => handle_device_event(event, &mut self).await,
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs one websocket connection as three tasks: a reader parsing frames, a
+/// writer draining the outgoing queue and the navigation task. When any of
+/// them ends the others are stopped.
+pub async fn handle_session(socket: WebSocket, remote: Option<SocketAddr>, state: ServerState) {
+    let rpc = ClientRpc::new(thread_rng().gen());
+    let remote = remote.map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+    let span = tracing::info_span!("session", instance = rpc.instance(), %remote);
+    run_session(socket, rpc, state).instrument(span).await
+}
+
+async fn run_session(socket: WebSocket, rpc: ClientRpc, state: ServerState) {
+    info!("session opened");
+
+    let (sink, stream) = socket.split();
+    let registry = state.registry;
+    let device = registry.register(rpc.instance());
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+    let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+
+    let _ = outgoing_tx.send(build_welcome(&generate_id()));
+
+    let tracer = FrameTracer {
+        instance: rpc.instance(),
+        trace: state.trace,
+    };
+    let mut writer = tokio::spawn(
+        write_messages(sink, outgoing_rx, tracer.clone()).instrument(tracing::Span::current()),
+    );
+    let mut reader = tokio::spawn(
+        read_messages(
+            stream,
+            rpc.clone(),
+            incoming_tx,
+            outgoing_tx.clone(),
+            tracer,
+        )
+        .instrument(tracing::Span::current()),
+    );
+    let session = Session::new(
+        rpc.clone(),
+        outgoing_tx,
+        registry.clone(),
+        device,
+        state.buttons,
+        state.profiles,
+    );
+    let mut navigation = tokio::spawn(
+        session
+            .run(incoming_rx)
+            .instrument(tracing::Span::current()),
+    );
+
+    tokio::select! {
+        _ = &mut reader => {
+            info!("session closed by client");
+            navigation.abort();
+            let _ = navigation.await;
+            // Dropping the session closed the queue, let the writer finish it
+            let _ = tokio::time::timeout(FLUSH_TIMEOUT, &mut writer).await;
+            writer.abort();
+        }
+        _ = &mut writer => {
+            info!("session writer stopped");
+            reader.abort();
+            navigation.abort();
+        }
+        _ = &mut navigation => {
+            info!("session navigation stopped");
+            reader.abort();
+            let _ = tokio::time::timeout(FLUSH_TIMEOUT, &mut writer).await;
+            writer.abort();
+        }
+    }
+
+    rpc.close();
+    registry.unregister(rpc.instance());
+}
+
+/// Logs frames and copies them to the trace file
+#[derive(Clone)]
+struct FrameTracer {
+    instance: u32,
+    trace: Option<Arc<FrameTrace>>,
+}
+impl FrameTracer {
+    fn record(&self, dir: Direction, frame: &str) {
+        debug!(?dir, frame, "wamp");
+        if let Some(trace) = &self.trace {
+            trace.record(self.instance, dir, frame);
+        }
+    }
+}
+
+/// Sends queued messages until the queue is closed, then closes the socket
+async fn write_messages(
+    mut sink: SplitSink<WebSocket, Message>,
+    mut outgoing: mpsc::UnboundedReceiver<wamp::Message>,
+    tracer: FrameTracer,
+) {
+    while let Some(msg) = outgoing.recv().await {
+        let text = msg.to_text();
+        tracer.record(Direction::Out, &text);
+        if let Err(err) = sink.send(Message::text(text)).await {
+            warn!(%err, "websocket error");
+            return;
+        }
+    }
+    let _ = sink.close().await;
+}
+
+/// Parses incoming frames, resolving replies to our own calls right away so
+/// the session can await them while it is busy handling something else.
+/// Calls which do not parse are answered here, the session never sees them.
+async fn read_messages(
+    mut receiver: SplitStream<WebSocket>,
+    rpc: ClientRpc,
+    incoming: mpsc::UnboundedSender<wamp::Message>,
+    outgoing: mpsc::UnboundedSender<wamp::Message>,
+    tracer: FrameTracer,
+) {
+    while let Some(result) = receiver.next().await {
+        let msg = match result {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!(%err, "websocket error");
+                break;
+            }
+        };
+        if msg.is_close() {
+            break;
+        }
+        let text = match msg.to_str() {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        tracer.record(Direction::In, text);
+
+        let msg = match wamp::Message::from_text(text) {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!(%err, "invalid message");
+                if let Some(call_id) = wamp::malformed_call_id(text) {
+                    let err = CallError::InvalidArgument(err.to_string());
+                    let reply = wamp::Message::call_error(&call_id, &err.uri(), &err.description());
+                    let _ = outgoing.send(reply);
+                }
+                continue;
+            }
+        };
+        if rpc.resolve(&msg) {
+            continue;
+        }
+        if incoming.send(msg).is_err() {
+            break;
+        }
+    }
+
+    rpc.close();
+}
+
+async fn handle_device_event(event: spnav_event, session: &mut Session) {
+    let motion = match event {
+        spnav_event::Motion(motion) => motion,
+        spnav_event::Button(button) => {
+            handle_button(button, session);
+            return;
+        }
+    };
+    let motion = match session.profile() {
+        Some(profile) => profile.apply(&motion),
+        None => motion,
+    };
+
+    // Nothing would consume it, it must not pile up for the next frame
+    if !session.subscribed {
+        return;
+    }
+    session.motion.push(&motion, Instant::now());
+
+    if !session.moving && !session.motion.is_idle() {
+        session.moving = true;
+        if let Err(err) = start_motion(session).await {
+            warn!(%err, "cannot start motion");
+            session.moving = false;
+            session.motion.discard(Instant::now());
+        }
+    }
+}
+
+/*
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"t3bLavZGeDHtHSly","self:update","","keyPress",2]]
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"t3bLavZGeDHtHSly","self:update","","commands.activeCommand","ID_FIT"]]
+ */
+/// Forwards a button as the virtual key it is mapped to, or runs the command
+/// bound to it on press. Profile bindings come before the configured ones.
+/// Unmapped buttons are dropped, the client has no use for raw button numbers.
+fn handle_button(button: spnav_event_button, session: &mut Session) {
+    let profile = session.profile();
+    let binding = profile
+        .as_ref()
+        .and_then(|profile| profile.buttons.get(button.bnum))
+        .or_else(|| session.buttons.get(button.bnum))
+        .cloned();
+    let binding = match binding {
+        Some(binding) => binding,
+        None => {
+            debug!(button = button.bnum, "button not mapped");
+            return;
+        }
+    };
+    if !session.subscribed {
+        return;
+    }
+    match binding {
+        Binding::Mode(_) | Binding::NextMode if button.press => {
+            let mode = match binding {
+                Binding::Mode(mode) => mode,
+                _ => session.mode().next(),
+            };
+            info!(?mode, "navigation mode changed");
+            session.mode = Some(mode);
+            session.view.center = navigation::center(mode, &session.properties);
+            session.show_pivot();
+        }
+        Binding::Mode(_) | Binding::NextMode => {}
+        Binding::Key(key) => {
+            let property = if button.press {
+                "keyPress"
+            } else {
+                "keyRelease"
+            };
+            debug!(button = button.bnum, key = key.0, property, "key");
+            session.send_update(property, json!(key.0));
+        }
+        Binding::Command(id) if button.press => {
+            let known = session
+                .registry
+                .with_info(session.rpc.instance(), |info| {
+                    info.commands.action(&id).is_some()
+                })
+                .unwrap_or(false);
+            // Sent anyway, the application may accept commands it does not list
+            debug!(button = button.bnum, command = %id, known, "command");
+            session.send_update("commands.activeCommand", json!(id));
+        }
+        Binding::Command(_) => {}
+    }
+}
+
+/// Reads the state navigation depends on once the client subscribed
+async fn handshake(session: &mut Session) -> Result<(), RpcError> {
+    for key in [
+        "selection.empty",
+        "view.perspective",
+        "coordinateSystem",
+        "model.extents",
+        "view.extents",
+    ] {
+        session.read_optional(key).await?;
+    }
+    if session.properties.selection_empty == Some(false) {
+        session.read_optional("selection.extents").await?;
+    }
+    session.read("view.affine").await?;
+    session.read_optional("view.target").await?;
+
+    debug!(projection = ?Projection::of(&session.properties), "handshake done");
+    session.reset_camera();
+    Ok(())
+}
+
+/// Refreshes the camera, then asks the client for frames
+async fn start_motion(session: &mut Session) -> Result<(), RpcError> {
+    session.read_optional("selection.empty").await?;
+    if session.properties.selection_empty == Some(false) {
+        session.read_optional("selection.extents").await?;
+    }
+    session.read("view.affine").await?;
+    session.read_optional("view.target").await?;
+    session.read_optional("pivot.user").await?;
+    if session.properties.pivot_user == Some(true) {
+        session.read_optional("pivot.position").await?;
+    }
+    session.read_optional("view.perspective").await?;
+    if Projection::of(&session.properties) == Projection::Orthographic {
+        session.read_optional("view.extents").await?;
+    }
+    // Bounds the dolly, or is scaled along with the extents
+    session.read_optional("view.frustum").await?;
+    hit_test(session).await?;
+    session.reset_camera();
+    session.show_pivot();
+
+    session.update("motion", json!(true)).await?;
+    Ok(())
+}
+
+/*
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"t3bLavZGeDHtHSly","self:update","","hit.lookfrom",[0,0,5]]]
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"t3bLavZGeDHtHSly","self:update","","hit.direction",[0,0,-1]]]
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"t3bLavZGeDHtHSly","self:update","","hit.aperture",0.1]]
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"t3bLavZGeDHtHSly","self:update","","hit.selectionOnly",false]]
+[8,"3dconnexion:3dcontroller/6884113743086",[2,"hRx0qv2Sa1jCe4bB","self:read","","hit.lookat"]]
+ */
+/// Asks the application what is under the centre of the screen, so object
+/// mode can orbit it. Only asked when the motion rotates, panning and
+/// zooming leave the pivot alone. The ray is sent without waiting, the
+/// client answers the read after applying it. Nothing hit, or a user pivot,
+/// leaves the pivot to the extents.
+async fn hit_test(session: &mut Session) -> Result<(), RpcError> {
+    session.properties.hit_lookat = None;
+    if !session.hit_testing
+        || session.mode() != Mode::Object
+        || !session.motion.is_rotating()
+        || session.properties.pivot_user == Some(true)
+    {
+        return Ok(());
+    }
+    if Projection::of(&session.properties) == Projection::Perspective {
+        // The aperture is a share of the field of view, else of the frustum
+        session.read_optional("view.fov").await?;
+    }
+    let ray = match navigation::hit_ray(&session.properties) {
+        Some(ray) => ray,
+        None => return Ok(()),
+    };
+    let selection_only = session.properties.selection_empty == Some(false);
+    session.send_update("hit.lookfrom", json!(ray.lookfrom));
+    session.send_update("hit.direction", json!(ray.direction));
+    session.send_update("hit.aperture", json!(ray.aperture));
+    session.send_update("hit.selectionOnly", json!(selection_only));
+
+    match session.read("hit.lookat").await {
+        Ok(lookat) => {
+            debug!(%lookat, "hit test");
+            Ok(())
+        }
+        Err(err @ (RpcError::Failed { .. } | RpcError::InvalidValue(_))) => {
+            debug!(%err, "client cannot hit-test");
+            session.hit_testing = false;
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn handle_msg(msg: wamp::Message, session: &mut Session) {
+    let ret = match msg {
+        wamp::Message::Welcome { .. } => return, // Server
+        wamp::Message::Prefix { prefix, uri } => {
+            session.prefixes.register(&prefix, &uri);
+            return;
+        }
+        wamp::Message::Call {
+            call_id,
+            proc_uri,
+            args,
+        } => {
+            let proc_uri = session.prefixes.resolve(&proc_uri);
+            match handle_call(&call_id, &proc_uri, &args, session).await {
+                Ok(ret) => ret,
+                Err(err) => {
+                    warn!(procedure = %proc_uri, ?args, error = ?err, "call failed");
+                    err.to_message(&call_id, &proc_uri)
+                }
+            }
+        }
+        // Replies to our own calls are resolved by the reader, these came too late
+        wamp::Message::CallResult { call_id, .. } => {
+            debug!(call_id, "late call result");
+            return;
+        }
+        wamp::Message::CallError {
+            call_id,
+            error_uri,
+            error_desc,
+            ..
+        } => {
+            debug!(call_id, error_uri, error_desc, "late call error");
+            return;
+        }
+        wamp::Message::Subscribe { topic_uri } => {
+            let topic_uri = session.prefixes.resolve(&topic_uri);
+            info!(topic = %topic_uri, "subscribed");
+
+            // Init variables
+            // [8,"3dconnexion:3dcontroller/6884113743086",[2,"t3bLavZGeDHtHSly","self:update","","motion",true]]
+
+            session.subscribed = true;
+            if let Err(err) = handshake(session).await {
+                warn!(%err, "handshake failed");
+            }
+
+            return;
+        }
+        wamp::Message::Unsubscribe { topic_uri } => {
+            let topic_uri = session.prefixes.resolve(&topic_uri);
+            debug!(topic = %topic_uri, "unsubscribed");
+            return;
+        }
+        wamp::Message::Publish { topic_uri, .. } => {
+            let topic_uri = session.prefixes.resolve(&topic_uri);
+            debug!(topic = %topic_uri, "ignoring publish");
+            return;
+        }
+        wamp::Message::Event { .. } => return, // Server
+    };
+
+    session.send(ret);
+}
+
+/// Reasons for answering a client call with a CALLERROR
+#[derive(Debug)]
+enum CallError {
+    UnknownProcedure,
+    InvalidArgument(String),
+    UnknownClass(String),
+    Internal(String),
+}
+impl CallError {
+    /// Stable identifier clients can match on
+    fn uri(&self) -> String {
+        let name = match self {
+            CallError::UnknownProcedure => "unknown-procedure",
+            CallError::InvalidArgument(_) => "invalid-argument",
+            CallError::UnknownClass(_) => "unknown-class",
+            CallError::Internal(_) => "internal",
+        };
+        format!("{ERROR_NAMESPACE}{name}")
+    }
+
+    fn description(&self) -> String {
+        match self {
+            CallError::UnknownProcedure => "unknown procedure".to_string(),
+            CallError::InvalidArgument(reason) => format!("invalid argument: {reason}"),
+            CallError::UnknownClass(class) => format!("cannot create unknown class {class}"),
+            CallError::Internal(reason) => format!("internal error: {reason}"),
+        }
+    }
+
+    fn to_message(&self, call_id: &str, proc_uri: &str) -> wamp::Message {
+        wamp::Message::call_error(call_id, &self.uri(), &self.description())
+            .with_details(json!({ "procedure": proc_uri }))
+    }
+}
+
+/*
+[2,"0.wu6w9bnqe4","3dx_rpc:create","3dconnexion:3dmouse","0.6.0"]
+[2,"0.h2jd78cvemc","3dx_rpc:update","3dconnexion:3dcontroller/6884113743086",{"commands":{"activeSet":"Part Studio"}}]
+[2,"0.pim5f32a7ff","3dx_rpc:update","3dconnexion:3dcontroller/6884113743086",{"focus":true}]
+ */
+async fn handle_call(
+    msg_id: &str,
+    fn_name: &str,
+    args: &[Value],
+    session: &mut Session,
+) -> Result<wamp::Message, CallError> {
+    let procedure = fn_name.strip_prefix(RPC_NAMESPACE).unwrap_or_default();
+
+    Ok(match procedure {
+        "create" => {
+            let class = args.first().and_then(|v| v.as_str()).ok_or_else(|| {
+                CallError::InvalidArgument("expected the class name as a string".to_string())
+            })?;
+            match class {
+                "3dconnexion:3dmouse" => {
+                    build_result(msg_id, json!({ "connexion": generate_id() }))
+                }
+                "3dconnexion:3dcontroller" => {
+                    let name = args
+                        .get(2)
+                        .and_then(|options| options.get("name"))
+                        .and_then(Value::as_str)
+                        .map(str::to_string);
+                    session.name = name.clone();
+                    session
+                        .registry
+                        .update_info(session.rpc.instance(), |info| info.name = name);
+                    build_result(msg_id, json!({ "instance": session.rpc.instance() }))
+                }
+                _ => return Err(CallError::UnknownClass(class.to_string())),
+            }
+        }
+        "update" => {
+            let map = match args.get(1) {
+                Some(Value::Object(map)) => map,
+                _ => {
+                    return Err(CallError::InvalidArgument(
+                        "expected an object of properties".to_string(),
+                    ))
+                }
+            };
+            let invalid = |err: PropertyError| {
+                CallError::InvalidArgument(format!("{} must be {}", err.key, err.expected))
+            };
+            session.properties.update(map).map_err(invalid)?;
+            if map.contains_key("commands") || map.contains_key("images") {
+                session
+                    .registry
+                    .update_info(session.rpc.instance(), |info| info.commands.update(map))
+                    .transpose()
+                    .map_err(invalid)?;
+            }
+
+            if let Some(focus) = map.get("focus").and_then(Value::as_bool) {
+                session.registry.set_focus(session.rpc.instance(), focus);
+            }
+            if map.contains_key("frame") && session.properties.frame_time.is_some() {
+                handle_frame(session).await?;
+            }
+
+            build_result(msg_id, json!({}))
+        }
+        "read" => {
+            let key = args.get(1).and_then(|v| v.as_str()).ok_or_else(|| {
+                CallError::InvalidArgument("expected the property name as a string".to_string())
+            })?;
+            build_result(msg_id, session.properties.get(key).unwrap_or(Value::Null))
+        }
+        _ => return Err(CallError::UnknownProcedure),
+    })
+}
+
+/// Applies the motion accumulated since the previous frame in one transaction,
+/// or tells the client to stop animating once the device is back at rest.
+async fn handle_frame(session: &mut Session) -> Result<(), CallError> {
+    if !session.moving {
+        return Ok(());
+    }
+    let motion = match session.motion.take(Instant::now()) {
+        Some(motion) => motion,
+        None => {
+            if session.motion.is_idle() {
+                session.moving = false;
+                session.show_pivot();
+                session.send_update("motion", json!(false));
+            }
+            return Ok(());
+        }
+    };
+
+    let mode = session.mode();
+    let scene = Scene::of(&session.properties);
+    let mut view = session.view;
+    navigation::step(mode, &mut view, &scene, &motion);
+    trace!(?mode, ?motion, ?view, "navigation step");
+
+    // Keep the last good camera instead of breaking the client's view
+    let extents = view
+        .extents
+        .iter()
+        .flat_map(|e| e.min.into_iter().chain(e.max));
+    let frustum = view
+        .frustum
+        .iter()
+        .flat_map(|f| [f.left, f.right, f.bottom, f.top]);
+    if !view
+        .affine
+        .into_iter()
+        .chain(extents)
+        .chain(frustum)
+        .all(f32::is_finite)
+    {
+        return Err(CallError::Internal(
+            "navigation produced a non-finite view".to_string(),
+        ));
+    }
+    let zoomed = view.extents != session.view.extents;
+    let frustum_zoomed = view.frustum != session.view.frustum;
+    // Panning in target mode carries the target along
+    let target = view
+        .center
+        .filter(|center| mode == Mode::Target && session.properties.view_target != Some(*center));
+    session.view = view;
+
+    session.send_update("transaction", json!(session.transactions));
+    session.transactions += 1;
+    session.properties.view_affine = Some(view.affine);
+    session.send_update("view.affine", json!(view.affine));
+    if let Some(target) = target {
+        session.properties.view_target = Some(target);
+        session.send_update("view.target", json!(target));
+    }
+    if zoomed {
+        // Orthographic zoom changes what is visible rather than the camera
+        session.properties.view_extents = view.extents;
+        if let Some(value) = session.properties.get("view.extents") {
+            session.send_update("view.extents", value);
+        }
+    }
+    if frustum_zoomed {
+        session.properties.view_frustum = view.frustum;
+        if let Some(value) = session.properties.get("view.frustum") {
+            session.send_update("view.frustum", value);
+        }
+    }
+    session.show_pivot();
+    session.send_update("transaction", json!(0));
+
+    Ok(())
+}
+
+/// Clients which call `3dx_rpc:create` without registering the prefix first
+/// still mean the 3Dconnexion namespace
+fn default_prefixes() -> wamp::Prefixes {
+    let mut prefixes = wamp::Prefixes::default();
+    prefixes.register("3dx_rpc", RPC_NAMESPACE);
+    prefixes
+}
+
+/*
+[0,"8GXm6SS4smp3Ai0e",1,"Nl-Proxy v1.4.3.19386 Copyright 2013-2022 3Dconnexion. All rights reserved."]
+ */
+fn build_welcome(id: &str) -> wamp::Message {
+    wamp::Message::Welcome {
+        session_id: id.to_string(),
+        protocol_version: wamp::PROTOCOL_VERSION,
+        server_ident: "Nl-Proxy v1.4.3.19386 Copyright 2013-2022 3Dconnexion. All rights reserved."
+            .to_string(),
+    }
+}
+
+/*
+[3,"0.pim5f32a7ff",{}]
+ */
+fn build_result(id: &str, data: Value) -> wamp::Message {
+    wamp::Message::call_result(id, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_error_reply() {
+        let msg = CallError::UnknownClass("3dconnexion:3dkeyboard".to_string())
+            .to_message("0.wu6w9bnqe4", "wss://127.51.68.120/3dconnexion#create");
+        assert_eq!(
+            msg.to_text(),
+            r#"[4,"0.wu6w9bnqe4","wss://127.51.68.120/3dconnexion/error#unknown-class","cannot create unknown class 3dconnexion:3dkeyboard",{"procedure":"wss://127.51.68.120/3dconnexion#create"}]"#
+        );
+    }
+}
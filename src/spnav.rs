@@ -0,0 +1,385 @@
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+    sync::broadcast,
+};
+
+pub const SPNAV_SOCK_PATH: &str = "/var/run/spnav.sock";
+
+pub const SPNAV_EVENT_MOTION: i32 = 1;
+pub const SPNAV_EVENT_BUTTON: i32 = 2;
+
+/// Every packet on the socket (events, requests and responses) is 8 native i32
+const PACKET_LEN: usize = 8;
+
+// Event ids sent by the daemon in data[0]
+const UEV_MOTION: i32 = 0;
+const UEV_PRESS: i32 = 1;
+const UEV_RELEASE: i32 = 2;
+
+// Protocol v1 requests
+const REQ_TAG: i32 = 0x7faa0000;
+const REQ_TAG_MASK: i32 = 0xffff0000u32 as i32;
+const REQ_SET_NAME: i32 = 0x1000;
+const REQ_CHANGE_PROTO: i32 = 0x5500;
+const MAX_PROTO_VER: i32 = 1;
+
+/// Legacy daemons never answer requests, so the handshake gives up after this
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(250);
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+const CLIENT_NAME: &str = "spacenav-web";
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct spnav_event_motion {
+    pub event_type: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub rx: i32,
+    pub ry: i32,
+    pub rz: i32,
+    pub period: u32,
+    // int *data;
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct spnav_event_button {
+    pub event_type: i32,
+    pub press: bool,
+    pub bnum: i32,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum spnav_event {
+    Motion(spnav_event_motion),
+    Button(spnav_event_button),
+}
+
+type Packet = [i32; PACKET_LEN];
+
+fn decode_event(data: &Packet) -> Option<spnav_event> {
+    match data[0] {
+        UEV_MOTION => Some(spnav_event::Motion(spnav_event_motion {
+            event_type: SPNAV_EVENT_MOTION,
+            x: data[1],
+            y: data[2],
+            z: data[3],
+            rx: data[4],
+            ry: data[5],
+            rz: data[6],
+            period: data[7] as u32,
+        })),
+        UEV_PRESS | UEV_RELEASE => Some(spnav_event::Button(spnav_event_button {
+            event_type: SPNAV_EVENT_BUTTON,
+            press: data[0] == UEV_PRESS,
+            bnum: data[1],
+        })),
+        // Device and config change notifications are not interesting to us
+        _ => None,
+    }
+}
+
+fn is_response(data: &Packet) -> bool {
+    data[0] & REQ_TAG_MASK == REQ_TAG
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    /// Negotiated protocol version, 0 for legacy daemons
+    proto: i32,
+    /// Events which arrived while waiting for a response
+    pending: VecDeque<Packet>,
+}
+impl Connection {
+    pub async fn connect(path: &Path) -> io::Result<Connection> {
+        let stream = UnixStream::connect(path).await?;
+        let mut conn = Connection {
+            stream,
+            proto: 0,
+            pending: VecDeque::new(),
+        };
+
+        let mut req: Packet = [0; PACKET_LEN];
+        req[0] = REQ_TAG | REQ_CHANGE_PROTO;
+        req[1] = MAX_PROTO_VER;
+        if let Some(resp) = conn.request(req, HANDSHAKE_TIMEOUT).await? {
+            if resp[7] == 0 {
+                conn.proto = resp[1].clamp(0, MAX_PROTO_VER);
+            }
+        }
+
+        if conn.proto >= 1 {
+            conn.set_name(CLIENT_NAME).await?;
+        }
+
+        Ok(conn)
+    }
+
+    pub fn protocol(&self) -> i32 {
+        self.proto
+    }
+
+    /// Waits for the next motion or button event
+    pub async fn next_event(&mut self) -> io::Result<spnav_event> {
+        loop {
+            let data = match self.pending.pop_front() {
+                Some(data) => data,
+                None => self.read_packet().await?,
+            };
+            if is_response(&data) {
+                continue;
+            }
+            if let Some(event) = decode_event(&data) {
+                return Ok(event);
+            }
+        }
+    }
+
+    async fn set_name(&mut self, name: &str) -> io::Result<()> {
+        // Names are sent in chunks of 24 bytes, data[7] holds the remaining length
+        let bytes = name.as_bytes();
+        let mut offset = 0;
+        loop {
+            let mut req: Packet = [0; PACKET_LEN];
+            req[0] = REQ_TAG | REQ_SET_NAME;
+            let chunk = &bytes[offset..bytes.len().min(offset + 24)];
+            let mut buf = [0u8; 24];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            for (i, word) in buf.chunks(4).enumerate() {
+                req[i + 1] = i32::from_ne_bytes(word.try_into().unwrap());
+            }
+            req[7] = (bytes.len() - offset) as i32;
+            if offset > 0 {
+                req[7] |= 0x10000;
+            }
+            self.request(req, HANDSHAKE_TIMEOUT).await?;
+
+            offset += chunk.len();
+            if offset >= bytes.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Sends a request and returns the matching response, or None on timeout
+    async fn request(&mut self, req: Packet, timeout: Duration) -> io::Result<Option<Packet>> {
+        self.write_packet(&req).await?;
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let data = match tokio::time::timeout_at(deadline, self.read_packet()).await {
+                Ok(data) => data?,
+                Err(_) => return Ok(None),
+            };
+            if data[0] == req[0] {
+                return Ok(Some(data));
+            }
+            if !is_response(&data) {
+                self.pending.push_back(data);
+            }
+        }
+    }
+
+    async fn read_packet(&mut self) -> io::Result<Packet> {
+        let mut buf = [0u8; PACKET_LEN * 4];
+        self.stream.read_exact(&mut buf).await?;
+
+        let mut data: Packet = [0; PACKET_LEN];
+        for (i, word) in buf.chunks(4).enumerate() {
+            data[i] = i32::from_ne_bytes(word.try_into().unwrap());
+        }
+        Ok(data)
+    }
+
+    async fn write_packet(&mut self, data: &Packet) -> io::Result<()> {
+        let mut buf = [0u8; PACKET_LEN * 4];
+        for (i, word) in data.iter().enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&word.to_ne_bytes());
+        }
+        self.stream.write_all(&buf).await
+    }
+}
+
+/// Connects to spacenavd and forwards its events until the process exits,
+/// reconnecting whenever the daemon goes away.
+pub async fn run(path: PathBuf, events: broadcast::Sender<spnav_event>) {
+    loop {
+        let mut conn = match Connection::connect(&path).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!(err = %e, "cannot connect to spacenavd");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        tracing::info!(protocol = conn.protocol(), "connected to spacenavd");
+
+        loop {
+            match conn.next_event().await {
+                // Having no session listening is not an error
+                Ok(event) => {
+                    let _ = events.send(event);
+                }
+                Err(e) => {
+                    tracing::warn!(err = %e, "spacenavd connection lost");
+                    break;
+                }
+            }
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("spnav-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn to_bytes(packets: &[Packet]) -> Vec<u8> {
+        packets
+            .iter()
+            .flat_map(|p| p.iter().flat_map(|w| w.to_ne_bytes()))
+            .collect()
+    }
+
+    // Two motion packets followed by a press and release of button 0
+    const RECORDED: [Packet; 4] = [
+        [0, 12, -40, 7, 0, 3, -2, 16],
+        [0, 110, -214, 31, 5, 18, -9, 16],
+        [1, 0, 0, 0, 0, 0, 0, 0],
+        [2, 0, 0, 0, 0, 0, 0, 0],
+    ];
+
+    fn expected() -> Vec<spnav_event> {
+        vec![
+            spnav_event::Motion(spnav_event_motion {
+                event_type: SPNAV_EVENT_MOTION,
+                x: 12,
+                y: -40,
+                z: 7,
+                rx: 0,
+                ry: 3,
+                rz: -2,
+                period: 16,
+            }),
+            spnav_event::Motion(spnav_event_motion {
+                event_type: SPNAV_EVENT_MOTION,
+                x: 110,
+                y: -214,
+                z: 31,
+                rx: 5,
+                ry: 18,
+                rz: -9,
+                period: 16,
+            }),
+            spnav_event::Button(spnav_event_button {
+                event_type: SPNAV_EVENT_BUTTON,
+                press: true,
+                bnum: 0,
+            }),
+            spnav_event::Button(spnav_event_button {
+                event_type: SPNAV_EVENT_BUTTON,
+                press: false,
+                bnum: 0,
+            }),
+        ]
+    }
+
+    async fn read_events(conn: &mut Connection, n: usize) -> Vec<spnav_event> {
+        let mut events = Vec::new();
+        for _ in 0..n {
+            events.push(conn.next_event().await.unwrap());
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn legacy_daemon() {
+        let path = socket_path("legacy");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // Old daemons start streaming right away and never answer requests
+            stream.write_all(&to_bytes(&RECORDED)).await.unwrap();
+            let mut buf = [0u8; PACKET_LEN * 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream
+        });
+
+        let mut conn = Connection::connect(&path).await.unwrap();
+        assert_eq!(conn.protocol(), 0);
+        assert_eq!(read_events(&mut conn, RECORDED.len()).await, expected());
+
+        drop(server.await.unwrap());
+        assert!(conn.next_event().await.is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn protocol_v1_daemon() {
+        let path = socket_path("v1");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; PACKET_LEN * 4];
+            let mut name: Vec<u8> = Vec::new();
+            loop {
+                stream.read_exact(&mut buf).await.unwrap();
+                let mut req: Packet = [0; PACKET_LEN];
+                for (i, word) in buf.chunks(4).enumerate() {
+                    req[i] = i32::from_ne_bytes(word.try_into().unwrap());
+                }
+                let mut resp = req;
+                resp[7] = 0;
+                match req[0] & !REQ_TAG_MASK {
+                    REQ_CHANGE_PROTO => {
+                        assert_eq!(req[1], MAX_PROTO_VER);
+                        resp[1] = 1;
+                        // Interleave an event with the response
+                        stream.write_all(&to_bytes(&RECORDED[..1])).await.unwrap();
+                    }
+                    REQ_SET_NAME => {
+                        let remaining = (req[7] & 0xffff) as usize;
+                        name.extend(buf[4..4 + remaining.min(24)].iter());
+                        if remaining <= 24 {
+                            stream.write_all(&to_bytes(&[resp])).await.unwrap();
+                            break;
+                        }
+                    }
+                    _ => panic!("unexpected request {:#x}", req[0]),
+                }
+                stream.write_all(&to_bytes(&[resp])).await.unwrap();
+            }
+            assert_eq!(name, CLIENT_NAME.as_bytes());
+            stream.write_all(&to_bytes(&RECORDED[1..])).await.unwrap();
+            stream
+        });
+
+        let mut conn = Connection::connect(&path).await.unwrap();
+        assert_eq!(conn.protocol(), 1);
+        assert_eq!(read_events(&mut conn, RECORDED.len()).await, expected());
+
+        drop(server.await.unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+}
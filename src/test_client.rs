@@ -0,0 +1,224 @@
+//! Client side of the 3Dconnexion JS SDK, just enough to drive a session
+//! through a websocket the way the browser does
+
+use std::collections::HashMap;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{net::TcpStream, time::Duration};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::wamp;
+
+/// Longer than the server's own call timeout, a test failing on this is stuck
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+const RPC_NAMESPACE: &str = "wss://127.51.68.120/3dconnexion#";
+const CLIENT_ERROR: &str = "wss://127.51.68.120/3dconnexion/client#error";
+
+pub struct FakeClient {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: u32,
+    pub instance: Option<u32>,
+    /// What the client answers to `self:read`, changed by `self:update`
+    pub properties: HashMap<String, Value>,
+    /// Every `self:update` the server made, in order
+    pub updates: Vec<(String, Value)>,
+}
+
+impl FakeClient {
+    /// Connects and waits for the welcome
+    pub async fn connect(url: &str) -> FakeClient {
+        let (socket, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .expect("cannot connect to the server");
+        // Replies are tiny, Nagle would hold them back until the server acks
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream.set_nodelay(true).expect("cannot disable Nagle");
+        }
+        let mut client = FakeClient {
+            socket,
+            next_id: 0,
+            instance: None,
+            properties: HashMap::new(),
+            updates: Vec::new(),
+        };
+        match client.recv().await {
+            wamp::Message::Welcome { .. } => {}
+            msg => panic!("expected a welcome, got {msg:?}"),
+        }
+        client
+    }
+
+    /// The SDK's startup: creates the mouse and the controller, takes the
+    /// focus and subscribes to the controller's events
+    pub async fn open(&mut self, app_name: &str) {
+        self.send(wamp::Message::Prefix {
+            prefix: "3dx_rpc".to_string(),
+            uri: RPC_NAMESPACE.to_string(),
+        })
+        .await;
+
+        let mouse = self
+            .call(
+                "3dx_rpc:create",
+                vec![json!("3dconnexion:3dmouse"), json!("0.6.0")],
+            )
+            .await
+            .expect("cannot create 3dmouse");
+        let connexion = mouse["connexion"].clone();
+
+        let controller = self
+            .call(
+                "3dx_rpc:create",
+                vec![
+                    json!("3dconnexion:3dcontroller"),
+                    connexion,
+                    json!({ "version": "0.6.0", "name": app_name }),
+                ],
+            )
+            .await
+            .expect("cannot create 3dcontroller");
+        let instance = controller["instance"].as_u64().expect("no instance") as u32;
+        self.instance = Some(instance);
+
+        self.set_focus(true).await;
+        self.send(wamp::Message::Subscribe {
+            topic_uri: self.topic(),
+        })
+        .await;
+    }
+
+    fn topic(&self) -> String {
+        format!(
+            "3dconnexion:3dcontroller/{}",
+            self.instance.expect("controller not created")
+        )
+    }
+
+    /// `3dx_rpc:update` on our controller
+    pub async fn update(&mut self, properties: Value) -> Result<Value, String> {
+        let topic = self.topic();
+        self.call("3dx_rpc:update", vec![json!(topic), properties])
+            .await
+    }
+
+    pub async fn set_focus(&mut self, focus: bool) {
+        self.update(json!({ "focus": focus }))
+            .await
+            .expect("focus update failed");
+    }
+
+    /// Tells the server an animation frame is being drawn
+    pub async fn frame(&mut self, time: f64) {
+        self.update(json!({ "frame": { "time": time } }))
+            .await
+            .expect("frame update failed");
+    }
+
+    /// Calls a procedure, answering the server's calls until the reply
+    /// arrives. Errors are returned as their URI.
+    pub async fn call(&mut self, proc_uri: &str, args: Vec<Value>) -> Result<Value, String> {
+        self.next_id += 1;
+        let call_id = format!("0.{}", self.next_id);
+        self.send(wamp::Message::Call {
+            call_id: call_id.clone(),
+            proc_uri: proc_uri.to_string(),
+            args,
+        })
+        .await;
+        self.reply(&call_id).await
+    }
+
+    /// Sends a frame as it is, for calls the message model cannot express,
+    /// and waits for the reply to `call_id`
+    pub async fn call_raw(&mut self, frame: &str, call_id: &str) -> Result<Value, String> {
+        self.socket
+            .send(Message::Text(frame.to_string()))
+            .await
+            .expect("cannot send");
+        self.reply(call_id).await
+    }
+
+    async fn reply(&mut self, call_id: &str) -> Result<Value, String> {
+        loop {
+            match self.recv().await {
+                wamp::Message::CallResult {
+                    call_id: id,
+                    result,
+                } if id == call_id => return Ok(result),
+                wamp::Message::CallError {
+                    call_id: id,
+                    error_uri,
+                    ..
+                } if id == call_id => return Err(error_uri),
+                msg => self.handle(msg).await,
+            }
+        }
+    }
+
+    /// Processes server frames until it updates `key`, returning the value
+    pub async fn wait_update(&mut self, key: &str) -> Value {
+        loop {
+            let msg = self.recv().await;
+            let count = self.updates.len();
+            self.handle(msg).await;
+            if let Some((_, value)) = self.updates[count..].iter().find(|(k, _)| k == key) {
+                return value.clone();
+            }
+        }
+    }
+
+    /// Values of every update of `key` so far
+    pub fn updates_of(&self, key: &str) -> Vec<Value> {
+        self.updates
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    /// Answers `self:read` and `self:update` calls wrapped in events
+    async fn handle(&mut self, msg: wamp::Message) {
+        let event = match msg {
+            wamp::Message::Event { event, .. } => event,
+            msg => panic!("unexpected message {msg:?}"),
+        };
+        let call_id = event[1].as_str().expect("call without id").to_string();
+        let key = event[4].as_str().unwrap_or_default().to_string();
+        let reply = match event[2].as_str() {
+            Some("self:read") => match self.properties.get(&key) {
+                Some(value) => wamp::Message::call_result(&call_id, value.clone()),
+                None => wamp::Message::call_error(&call_id, CLIENT_ERROR, "no such property"),
+            },
+            Some("self:update") => {
+                let value = event[5].clone();
+                self.properties.insert(key.clone(), value.clone());
+                self.updates.push((key, value));
+                wamp::Message::call_result(&call_id, Value::Null)
+            }
+            _ => panic!("unexpected call {event}"),
+        };
+        self.send(reply).await;
+    }
+
+    async fn send(&mut self, msg: wamp::Message) {
+        self.socket
+            .send(Message::Text(msg.to_text()))
+            .await
+            .expect("cannot send");
+    }
+
+    async fn recv(&mut self) -> wamp::Message {
+        loop {
+            let msg = tokio::time::timeout(RECV_TIMEOUT, self.socket.next())
+                .await
+                .expect("server did not send anything")
+                .expect("connection closed")
+                .expect("websocket error");
+            if let Message::Text(text) = msg {
+                return wamp::Message::from_text(&text).expect("invalid WAMP message");
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{json, Value};
+use tokio::time::Instant;
+
+use crate::json_lines;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the client
+    In,
+    /// Sent by us
+    Out,
+}
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Direction> {
+        match s {
+            "in" => Some(Direction::In),
+            "out" => Some(Direction::Out),
+            _ => None,
+        }
+    }
+}
+
+/// One recorded frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub time: u64,
+    pub instance: u32,
+    pub dir: Direction,
+    pub frame: String,
+}
+
+/*
+Every WAMP frame of every session as JSON lines, with milliseconds since the
+trace was opened:
+{"dir":"in","frame":[5,"3dconnexion:3dcontroller/3847201"],"instance":3847201,"time":412}
+ */
+pub struct FrameTrace {
+    start: Instant,
+    file: Mutex<LineWriter<File>>,
+}
+impl FrameTrace {
+    /// Appends to the file, so restarts keep earlier traces
+    pub fn open(path: &Path) -> io::Result<Arc<FrameTrace>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Arc::new(FrameTrace {
+            start: Instant::now(),
+            file: Mutex::new(LineWriter::new(file)),
+        }))
+    }
+
+    pub fn record(&self, instance: u32, dir: Direction, frame: &str) {
+        let line = trace_line(
+            self.start.elapsed().as_millis() as u64,
+            instance,
+            dir,
+            frame,
+        );
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = writeln!(file, "{line}") {
+            tracing::warn!(%err, "cannot write WAMP trace");
+        }
+    }
+}
+
+/// Frames are embedded as JSON where they parse, and as strings otherwise
+fn trace_line(time_ms: u64, instance: u32, dir: Direction, frame: &str) -> String {
+    let frame = serde_json::from_str(frame).unwrap_or_else(|_| Value::from(frame));
+    json!({
+        "time": time_ms,
+        "instance": instance,
+        "dir": dir.as_str(),
+        "frame": frame,
+    })
+    .to_string()
+}
+
+pub fn parse_line(line: &str) -> Result<TraceEntry, String> {
+    let value: Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let frame = match &value["frame"] {
+        Value::String(frame) => frame.clone(),
+        Value::Null => return Err("frame is missing".to_string()),
+        frame => frame.to_string(),
+    };
+    Ok(TraceEntry {
+        time: value["time"]
+            .as_u64()
+            .ok_or("time must be a positive integer")?,
+        instance: value["instance"]
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or("instance must be a positive integer")?,
+        dir: value["dir"]
+            .as_str()
+            .and_then(Direction::parse)
+            .ok_or("dir must be \"in\" or \"out\"")?,
+        frame,
+    })
+}
+
+/// Reads a whole trace, reporting the first bad line by number
+pub fn load(path: &Path) -> io::Result<Vec<TraceEntry>> {
+    json_lines::load(path, parse_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            trace_line(
+                7,
+                42,
+                Direction::Out,
+                r#"[0,"8GXm6SS4smp3Ai0e",1,"Nl-Proxy"]"#
+            ),
+            r#"{"dir":"out","frame":[0,"8GXm6SS4smp3Ai0e",1,"Nl-Proxy"],"instance":42,"time":7}"#
+        );
+        let line = trace_line(8, 42, Direction::In, "[5,");
+        assert_eq!(line, r#"{"dir":"in","frame":"[5,","instance":42,"time":8}"#);
+        assert_eq!(
+            parse_line(&line),
+            Ok(TraceEntry {
+                time: 8,
+                instance: 42,
+                dir: Direction::In,
+                frame: "[5,".to_string(),
+            })
+        );
+        assert!(parse_line(r#"{"dir":"up","frame":[5],"instance":42,"time":8}"#).is_err());
+    }
+}
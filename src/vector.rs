@@ -1,9 +1,15 @@
-use crate::quat::{Quat, QuatOperationable};
-
 pub type Vector = [f32; 3];
 pub trait VectorOperationable {
     fn cross(&self, vb: &Vector) -> [f32; 3];
-    fn qrot(&mut self, quat: &Quat);
+    fn dot(&self, vb: &Vector) -> f32;
+    fn add(&self, vb: &Vector) -> Vector;
+    fn sub(&self, vb: &Vector) -> Vector;
+    fn scale(&self, s: f32) -> Vector;
+    fn length(&self) -> f32;
+    /// Unit vector in the same direction, None for the zero vector
+    fn normalized(&self) -> Option<Vector>;
+    /// Rotates around the unit vector `axis` by `angle` radians (Rodrigues)
+    fn rotated(&self, axis: &Vector, angle: f32) -> Vector;
 }
 
 impl VectorOperationable for Vector {
@@ -15,17 +21,35 @@ impl VectorOperationable for Vector {
         ]
     }
 
-    fn qrot(&mut self, quat: &Quat) {
-        let mut inv_q: Quat = quat.clone();
-        let mut tmp_q: Quat = quat.clone();
-        let vq: Quat = [self[0], self[1], self[2], 0.0];
+    fn dot(&self, vb: &Vector) -> f32 {
+        self[0] * vb[0] + self[1] * vb[1] + self[2] * vb[2]
+    }
+
+    fn add(&self, vb: &Vector) -> Vector {
+        [self[0] + vb[0], self[1] + vb[1], self[2] + vb[2]]
+    }
+
+    fn sub(&self, vb: &Vector) -> Vector {
+        [self[0] - vb[0], self[1] - vb[1], self[2] - vb[2]]
+    }
 
-        inv_q.invert();
-        tmp_q.mult(&vq);
-        tmp_q.mult(&inv_q);
+    fn scale(&self, s: f32) -> Vector {
+        [self[0] * s, self[1] * s, self[2] * s]
+    }
+
+    fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    fn normalized(&self) -> Option<Vector> {
+        let len = self.length();
+        (len > f32::EPSILON).then(|| self.scale(1.0 / len))
+    }
 
-        self[0] = tmp_q[0];
-        self[1] = tmp_q[1];
-        self[2] = tmp_q[2];
+    fn rotated(&self, axis: &Vector, angle: f32) -> Vector {
+        let (sin, cos) = angle.sin_cos();
+        self.scale(cos)
+            .add(&axis.cross(self).scale(sin))
+            .add(&axis.scale(axis.dot(self) * (1.0 - cos)))
     }
 }
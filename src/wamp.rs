@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+
+use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/*
+{
+  "_version": "0.8.2.1",
+  "browserNotSupportedMessage": "Browser does not support WebSockets (RFC6455)",
+  "_idchars": "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+  "_idlen": 16,
+  "_subprotocol": "wamp",
+  "_debugrpc": false,
+  "_debugpubsub": false,
+  "_debugws": false,
+  "_debugconnect": false,
+  "_MESSAGE_TYPEID_WELCOME": 0,
+  "_MESSAGE_TYPEID_PREFIX": 1,
+  "_MESSAGE_TYPEID_CALL": 2,
+  "_MESSAGE_TYPEID_CALL_RESULT": 3,
+  "_MESSAGE_TYPEID_CALL_ERROR": 4,
+  "_MESSAGE_TYPEID_SUBSCRIBE": 5,
+  "_MESSAGE_TYPEID_UNSUBSCRIBE": 6,
+  "_MESSAGE_TYPEID_PUBLISH": 7,
+  "_MESSAGE_TYPEID_EVENT": 8,
+  "CONNECTION_CLOSED": 0,
+  "CONNECTION_LOST": 1,
+  "CONNECTION_RETRIES_EXCEEDED": 2,
+  "CONNECTION_UNREACHABLE": 3,
+  "CONNECTION_UNSUPPORTED": 4,
+  "CONNECTION_UNREACHABLE_SCHEDULED_RECONNECT": 5,
+  "CONNECTION_LOST_SCHEDULED_RECONNECT": 6,
+  "_UA_FIREFOX": {},
+  "_UA_CHROME": {},
+  "_UA_CHROMEFRAME": {},
+  "_UA_WEBKIT": {},
+  "_UA_WEBOS": {}
+}
+ */
+
+pub const PROTOCOL_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// 0: Server greets client
+    Welcome = 0,
+    /// 1: Client greets server
+    Prefix = 1,
+    /// 2: Client calls server function
+    Call = 2,
+    /// 3: Return of server/client function
+    CallResult = 3,
+    /// 4: Client sends event notice to server
+    CallError = 4,
+    /// 5: Client joined room specified by server
+    Subscribe = 5,
+    /// 6
+    Unsubscribe = 6,
+    /// 7
+    Publish = 7,
+    /// 8: Server calls client function
+    Event = 8,
+}
+impl MessageType {
+    fn from_u64(value: u64) -> Option<MessageType> {
+        Some(match value {
+            0 => MessageType::Welcome,
+            1 => MessageType::Prefix,
+            2 => MessageType::Call,
+            3 => MessageType::CallResult,
+            4 => MessageType::CallError,
+            5 => MessageType::Subscribe,
+            6 => MessageType::Unsubscribe,
+            7 => MessageType::Publish,
+            8 => MessageType::Event,
+            _ => return None,
+        })
+    }
+}
+
+/// A WAMP v1 message with the field layout of the specification
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// [0, sessionId, protocolVersion, serverIdent]
+    Welcome {
+        session_id: String,
+        protocol_version: u64,
+        server_ident: String,
+    },
+    /// [1, prefix, URI]
+    Prefix { prefix: String, uri: String },
+    /// [2, callID, procURI, arg1, arg2, ...]
+    Call {
+        call_id: String,
+        proc_uri: String,
+        args: Vec<Value>,
+    },
+    /// [3, callID, result]
+    CallResult { call_id: String, result: Value },
+    /// [4, callID, errorURI, errorDesc(, errorDetails)]
+    CallError {
+        call_id: String,
+        error_uri: String,
+        error_desc: String,
+        error_details: Option<Value>,
+    },
+    /// [5, topicURI]
+    Subscribe { topic_uri: String },
+    /// [6, topicURI]
+    Unsubscribe { topic_uri: String },
+    /// [7, topicURI, event(, excludeMe | exclude, eligible)]
+    Publish {
+        topic_uri: String,
+        event: Value,
+        exclude: Option<Value>,
+        eligible: Option<Value>,
+    },
+    /// [8, topicURI, event]
+    Event { topic_uri: String, event: Value },
+}
+impl Message {
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Message::Welcome { .. } => MessageType::Welcome,
+            Message::Prefix { .. } => MessageType::Prefix,
+            Message::Call { .. } => MessageType::Call,
+            Message::CallResult { .. } => MessageType::CallResult,
+            Message::CallError { .. } => MessageType::CallError,
+            Message::Subscribe { .. } => MessageType::Subscribe,
+            Message::Unsubscribe { .. } => MessageType::Unsubscribe,
+            Message::Publish { .. } => MessageType::Publish,
+            Message::Event { .. } => MessageType::Event,
+        }
+    }
+
+    pub fn call_result(call_id: &str, result: Value) -> Message {
+        Message::CallResult {
+            call_id: call_id.to_string(),
+            result,
+        }
+    }
+
+    pub fn call_error(call_id: &str, error_uri: &str, error_desc: &str) -> Message {
+        Message::CallError {
+            call_id: call_id.to_string(),
+            error_uri: error_uri.to_string(),
+            error_desc: error_desc.to_string(),
+            error_details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Message {
+        if let Message::CallError { error_details, .. } = &mut self {
+            *error_details = Some(details);
+        }
+        self
+    }
+
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).expect("WAMP messages always serialize")
+    }
+
+    pub fn from_text(text: &str) -> serde_json::Result<Message> {
+        serde_json::from_str(text)
+    }
+}
+
+/// The callID of a frame which looks like a CALL but does not parse, so
+/// the caller can still be answered
+pub fn malformed_call_id(text: &str) -> Option<String> {
+    let frame: Vec<Value> = serde_json::from_str(text).ok()?;
+    if frame.first()?.as_u64()? != MessageType::Call as u64 {
+        return None;
+    }
+    frame.get(1)?.as_str().map(str::to_string)
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        seq.serialize_element(&(self.message_type() as u64))?;
+        match self {
+            Message::Welcome {
+                session_id,
+                protocol_version,
+                server_ident,
+            } => {
+                seq.serialize_element(session_id)?;
+                seq.serialize_element(protocol_version)?;
+                seq.serialize_element(server_ident)?;
+            }
+            Message::Prefix { prefix, uri } => {
+                seq.serialize_element(prefix)?;
+                seq.serialize_element(uri)?;
+            }
+            Message::Call {
+                call_id,
+                proc_uri,
+                args,
+            } => {
+                seq.serialize_element(call_id)?;
+                seq.serialize_element(proc_uri)?;
+                for arg in args {
+                    seq.serialize_element(arg)?;
+                }
+            }
+            Message::CallResult { call_id, result } => {
+                seq.serialize_element(call_id)?;
+                seq.serialize_element(result)?;
+            }
+            Message::CallError {
+                call_id,
+                error_uri,
+                error_desc,
+                error_details,
+            } => {
+                seq.serialize_element(call_id)?;
+                seq.serialize_element(error_uri)?;
+                seq.serialize_element(error_desc)?;
+                if let Some(details) = error_details {
+                    seq.serialize_element(details)?;
+                }
+            }
+            Message::Subscribe { topic_uri } | Message::Unsubscribe { topic_uri } => {
+                seq.serialize_element(topic_uri)?;
+            }
+            Message::Publish {
+                topic_uri,
+                event,
+                exclude,
+                eligible,
+            } => {
+                seq.serialize_element(topic_uri)?;
+                seq.serialize_element(event)?;
+                if let Some(exclude) = exclude {
+                    seq.serialize_element(exclude)?;
+                    if let Some(eligible) = eligible {
+                        seq.serialize_element(eligible)?;
+                    }
+                }
+            }
+            Message::Event { topic_uri, event } => {
+                seq.serialize_element(topic_uri)?;
+                seq.serialize_element(event)?;
+            }
+        }
+        seq.end()
+    }
+}
+
+/// CURIE prefixes registered with PREFIX messages, see
+/// https://wamp-proto.org/wamp_prior_versions/wamp_v1.html#curies
+#[derive(Debug, Clone, Default)]
+pub struct Prefixes {
+    map: HashMap<String, String>,
+}
+impl Prefixes {
+    pub fn register(&mut self, prefix: &str, uri: &str) {
+        self.map.insert(prefix.to_string(), uri.to_string());
+    }
+
+    /// Expands `prefix:suffix` if the prefix is known, anything else is
+    /// returned unchanged.
+    pub fn resolve(&self, uri: &str) -> String {
+        if let Some((prefix, suffix)) = uri.split_once(':') {
+            if let Some(base) = self.map.get(prefix) {
+                return format!("{base}{suffix}");
+            }
+        }
+        uri.to_string()
+    }
+}
+
+fn next_string<E: de::Error>(
+    fields: &mut impl Iterator<Item = Value>,
+    name: &str,
+) -> Result<String, E> {
+    match fields.next() {
+        Some(Value::String(s)) => Ok(s),
+        Some(_) => Err(E::custom(format!("{name} must be a string"))),
+        None => Err(E::custom(format!("missing {name}"))),
+    }
+}
+
+fn next_value<E: de::Error>(
+    fields: &mut impl Iterator<Item = Value>,
+    name: &str,
+) -> Result<Value, E> {
+    fields
+        .next()
+        .ok_or_else(|| E::custom(format!("missing {name}")))
+}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Message, D::Error> {
+        let mut fields = Vec::<Value>::deserialize(deserializer)?.into_iter();
+
+        let type_id = next_value::<D::Error>(&mut fields, "message type id")?
+            .as_u64()
+            .ok_or_else(|| de::Error::custom("message type id must be a number"))?;
+        let msg_type = MessageType::from_u64(type_id)
+            .ok_or_else(|| de::Error::custom(format!("unknown message type id {type_id}")))?;
+
+        let f = &mut fields;
+        let msg = match msg_type {
+            MessageType::Welcome => Message::Welcome {
+                session_id: next_string(f, "sessionId")?,
+                protocol_version: next_value::<D::Error>(f, "protocolVersion")?
+                    .as_u64()
+                    .ok_or_else(|| de::Error::custom("protocolVersion must be a number"))?,
+                server_ident: next_string(f, "serverIdent")?,
+            },
+            MessageType::Prefix => Message::Prefix {
+                prefix: next_string(f, "prefix")?,
+                uri: next_string(f, "URI")?,
+            },
+            MessageType::Call => Message::Call {
+                call_id: next_string(f, "callID")?,
+                proc_uri: next_string(f, "procURI")?,
+                args: f.collect(),
+            },
+            MessageType::CallResult => Message::CallResult {
+                call_id: next_string(f, "callID")?,
+                result: next_value(f, "result")?,
+            },
+            MessageType::CallError => Message::CallError {
+                call_id: next_string(f, "callID")?,
+                error_uri: next_string(f, "errorURI")?,
+                error_desc: next_string(f, "errorDesc")?,
+                error_details: f.next(),
+            },
+            MessageType::Subscribe => Message::Subscribe {
+                topic_uri: next_string(f, "topicURI")?,
+            },
+            MessageType::Unsubscribe => Message::Unsubscribe {
+                topic_uri: next_string(f, "topicURI")?,
+            },
+            MessageType::Publish => Message::Publish {
+                topic_uri: next_string(f, "topicURI")?,
+                event: next_value(f, "event")?,
+                exclude: f.next(),
+                eligible: f.next(),
+            },
+            MessageType::Event => Message::Event {
+                topic_uri: next_string(f, "topicURI")?,
+                event: next_value(f, "event")?,
+            },
+        };
+
+        if fields.next().is_some() {
+            return Err(de::Error::custom(format!(
+                "too many fields for {:?}",
+                msg.message_type()
+            )));
+        }
+
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(text: &str) -> Message {
+        let msg = Message::from_text(text).unwrap();
+        let again = Message::from_text(&msg.to_text()).unwrap();
+        assert_eq!(msg, again);
+        assert_eq!(
+            serde_json::from_str::<Value>(&msg.to_text()).unwrap(),
+            serde_json::from_str::<Value>(text).unwrap()
+        );
+        msg
+    }
+
+    #[test]
+    fn welcome() {
+        let msg = round_trip(
+            r#"[0,"8GXm6SS4smp3Ai0e",1,"Nl-Proxy v1.4.3.19386 Copyright 2013-2022 3Dconnexion. All rights reserved."]"#,
+        );
+        assert!(
+            matches!(msg, Message::Welcome { protocol_version: 1, ref session_id, .. } if session_id == "8GXm6SS4smp3Ai0e")
+        );
+    }
+
+    #[test]
+    fn call() {
+        let msg =
+            round_trip(r#"[2,"0.wu6w9bnqe4","3dx_rpc:create","3dconnexion:3dmouse","0.6.0"]"#);
+        assert_eq!(
+            msg,
+            Message::Call {
+                call_id: "0.wu6w9bnqe4".to_string(),
+                proc_uri: "3dx_rpc:create".to_string(),
+                args: vec![json!("3dconnexion:3dmouse"), json!("0.6.0")],
+            }
+        );
+
+        round_trip(
+            r#"[2,"0.h2jd78cvemc","3dx_rpc:update","3dconnexion:3dcontroller/6884113743086",{"commands":{"activeSet":"Part Studio"}}]"#,
+        );
+    }
+
+    #[test]
+    fn call_result_and_error() {
+        round_trip(r#"[3,"0.pim5f32a7ff",{}]"#);
+        round_trip(r#"[4,"0.pim5f32a7ff","http://example.com/error","failed"]"#);
+
+        let msg = Message::call_error("abc", "http://example.com/error", "failed")
+            .with_details(json!({"why": 1}));
+        assert_eq!(
+            msg.to_text(),
+            r#"[4,"abc","http://example.com/error","failed",{"why":1}]"#
+        );
+    }
+
+    #[test]
+    fn pubsub() {
+        round_trip(r#"[1,"3dx_rpc","http://3dconnexion.com/3dx_rpc#"]"#);
+        round_trip(r#"[5,"3dconnexion:3dcontroller/6884113743086"]"#);
+        round_trip(r#"[6,"3dconnexion:3dcontroller/6884113743086"]"#);
+        round_trip(r#"[7,"topic",{"a":1}]"#);
+        round_trip(r#"[7,"topic",{"a":1},true]"#);
+        round_trip(r#"[7,"topic",{"a":1},["x"],["y"]]"#);
+        round_trip(
+            r#"[8,"3dconnexion:3dcontroller/6884113743086",[2,"AEZWTPeAMmbGLPIB","self:read","","coordinateSystem"]]"#,
+        );
+    }
+
+    #[test]
+    fn prefixes() {
+        let mut prefixes = Prefixes::default();
+        assert_eq!(prefixes.resolve("3dx_rpc:create"), "3dx_rpc:create");
+
+        prefixes.register("3dx_rpc", "wss://127.51.68.120/3dconnexion#");
+        assert_eq!(
+            prefixes.resolve("3dx_rpc:create"),
+            "wss://127.51.68.120/3dconnexion#create"
+        );
+        assert_eq!(
+            prefixes.resolve("wss://127.51.68.120/3dconnexion#create"),
+            "wss://127.51.68.120/3dconnexion#create"
+        );
+        assert_eq!(
+            prefixes.resolve("3dconnexion:3dcontroller/42"),
+            "3dconnexion:3dcontroller/42"
+        );
+
+        prefixes.register("3dx_rpc", "http://example.com/rpc#");
+        assert_eq!(
+            prefixes.resolve("3dx_rpc:update"),
+            "http://example.com/rpc#update"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        assert!(Message::from_text("{}").is_err());
+        assert!(Message::from_text("[9,\"x\"]").is_err());
+        assert!(Message::from_text("[5]").is_err());
+        assert!(Message::from_text("[5,1]").is_err());
+        assert!(Message::from_text("[6,\"a\",\"b\"]").is_err());
+        assert!(Message::from_text("[3,\"id\"]").is_err());
+
+        let call = "[2,\"id\",123]";
+        assert!(Message::from_text(call).is_err());
+        assert_eq!(malformed_call_id(call).as_deref(), Some("id"));
+        assert_eq!(malformed_call_id("[2,5,\"x\"]"), None);
+        assert_eq!(malformed_call_id("[3,\"id\"]"), None);
+    }
+}